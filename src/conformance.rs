@@ -0,0 +1,332 @@
+//! Run the rules in `website/catalog` against their documented examples,
+//! checking the highlighted lines of `### Example` and the fixed code of `### Diff`.
+
+use crate::test::use_langs;
+use crate::wasm_lang::WasmLang;
+use crate::{apply_fixes, find_matches};
+use crate::grammar::Grammar;
+use ast_grep_config::{RuleConfig, SerializableRuleConfig};
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+struct CodeBlock {
+  heading: String,
+  info: String,
+  body: String,
+}
+
+fn code_blocks(markdown: &str) -> Vec<CodeBlock> {
+  let mut ret = vec![];
+  let mut heading = String::new();
+  let mut current: Option<CodeBlock> = None;
+  for line in markdown.lines() {
+    if let Some(block) = &mut current {
+      if line.trim_end() == "```" {
+        ret.extend(current.take());
+      } else {
+        block.body.push_str(line);
+        block.body.push('\n');
+      }
+    } else if let Some(info) = line.strip_prefix("```") {
+      current = Some(CodeBlock {
+        heading: heading.clone(),
+        info: info.trim().to_string(),
+        body: String::new(),
+      });
+    } else if let Some(title) = line.strip_prefix("### ") {
+      heading = title.trim().to_string();
+    }
+  }
+  ret
+}
+
+/// zero based line numbers in a fence info like `ts {1,3-5}`
+fn highlighted_lines(info: &str) -> Option<BTreeSet<usize>> {
+  let start = info.find('{')?;
+  let end = info.find('}')?;
+  let mut lines = BTreeSet::new();
+  for part in info[start + 1..end].split(',') {
+    let (from, to) = part.split_once('-').unwrap_or((part, part));
+    let (from, to): (usize, usize) = (from.trim().parse().ok()?, to.trim().parse().ok()?);
+    lines.extend(from - 1..to);
+  }
+  Some(lines)
+}
+
+/// the catalog follows the CLI, which accepts aliases and any casing
+fn normalize_lang(lang: &str) -> String {
+  let lang = lang.to_lowercase();
+  match lang.as_str() {
+    "js" | "jsx" => "javascript",
+    "ts" => "typescript",
+    "rs" => "rust",
+    "py" => "python",
+    "yml" => "yaml",
+    "rb" => "ruby",
+    "golang" => "go",
+    "c++" => "cpp",
+    _ => return lang,
+  }
+  .to_string()
+}
+
+/// split a shell command into words, honoring quotes and line continuations
+fn shell_words(command: &str) -> Vec<String> {
+  let mut words = vec![];
+  let mut word: Option<String> = None;
+  let mut quote = None;
+  let mut chars = command.chars();
+  while let Some(c) = chars.next() {
+    match (quote, c) {
+      (Some(q), c) if c == q => quote = None,
+      (Some(_), c) => word.get_or_insert_with(String::new).push(c),
+      (None, '\'' | '"') => {
+        quote = Some(c);
+        word.get_or_insert_with(String::new);
+      }
+      (None, '\\') => {
+        if let Some(next) = chars.next().filter(|n| *n != '\n') {
+          word.get_or_insert_with(String::new).push(next);
+        }
+      }
+      (None, c) if c.is_whitespace() => words.extend(word.take()),
+      (None, c) => word.get_or_insert_with(String::new).push(c),
+    }
+  }
+  words.extend(word);
+  words
+}
+
+/// translate an `ast-grep -p .. -r .. -l ..` command into a rule
+fn cli_rule(id: String, command: &str, default_lang: &str) -> Option<Value> {
+  let words = shell_words(command);
+  let mut words = words.iter();
+  let (mut pattern, mut rewrite, mut selector) = (None, None, None);
+  let mut lang = default_lang.to_string();
+  while let Some(word) = words.next() {
+    match word.as_str() {
+      "-p" | "--pattern" => pattern = words.next(),
+      "-r" | "--rewrite" => rewrite = words.next(),
+      "-l" | "--lang" => lang = normalize_lang(words.next()?),
+      "--selector" => selector = words.next(),
+      _ => {}
+    }
+  }
+  let pattern = match selector {
+    Some(selector) => json!({ "context": pattern?, "selector": selector }),
+    None => json!(pattern?),
+  };
+  let mut rule = json!({ "id": id, "language": lang, "rule": { "pattern": pattern } });
+  if let Some(rewrite) = rewrite {
+    rule["fix"] = json!(rewrite);
+  }
+  Some(rule)
+}
+
+/// source before and after the fix, from `[!code --]` and `[!code ++]` annotations
+fn split_diff(diff: &str) -> (String, String) {
+  let (mut before, mut after) = (vec![], vec![]);
+  for line in diff.lines() {
+    match line.find("[!code") {
+      Some(index) => {
+        let code = strip_comment(&line[..index]);
+        if line[index..].starts_with("[!code --]") {
+          before.push(code);
+        } else {
+          after.push(code);
+        }
+      }
+      None => {
+        before.push(line);
+        after.push(line);
+      }
+    }
+  }
+  (before.join("\n"), after.join("\n"))
+}
+
+fn strip_comment(code: &str) -> &str {
+  let code = code.trim_end();
+  for opener in ["{/*", "<!--", "/*", "//", "#"].iter() {
+    if let Some(rest) = code.strip_suffix(opener) {
+      return rest.trim_end();
+    }
+  }
+  code
+}
+
+/// the catalog diffs are formatted by hand, so only compare non-blank trimmed lines
+fn normalize_code(code: &str) -> Vec<&str> {
+  code.lines().map(str::trim).filter(|l| !l.is_empty()).collect()
+}
+
+struct CatalogEntry {
+  name: String,
+  rules: Vec<Value>,
+  example: Option<CodeBlock>,
+  diff: Option<CodeBlock>,
+}
+
+impl CatalogEntry {
+  fn read(path: &Path) -> Option<Self> {
+    let markdown = fs::read_to_string(path).ok()?;
+    let dir_lang = path.parent()?.file_name()?.to_str()?;
+    let name = format!("{}/{}", dir_lang, path.file_stem()?.to_str()?);
+    let mut rules = vec![];
+    let mut example = None;
+    let mut diff = None;
+    for block in code_blocks(&markdown) {
+      match block.heading.as_str() {
+        "YAML" if block.info.starts_with("yaml") || block.info.starts_with("yml") => {
+          for doc in block.body.split("\n---\n") {
+            rules.extend(serde_yaml::from_str::<Value>(doc).ok().filter(Value::is_object));
+          }
+        }
+        "Pattern" => {
+          let id = format!("{}-{}", name, rules.len());
+          rules.extend(cli_rule(id, &block.body, dir_lang));
+        }
+        "Example" if example.is_none() => example = Some(block),
+        "Diff" if diff.is_none() => diff = Some(block),
+        _ => {}
+      }
+    }
+    // the catalog directory names the language of rules without one
+    for rule in &mut rules {
+      let lang = rule["language"].as_str().unwrap_or(dir_lang);
+      rule["language"] = json!(normalize_lang(lang));
+    }
+    Some(Self {
+      name,
+      rules,
+      example,
+      diff,
+    })
+  }
+
+  fn languages(&self) -> Option<Vec<WasmLang>> {
+    let mut langs = vec![];
+    for rule in &self.rules {
+      let lang = WasmLang::from_str(rule["language"].as_str()?).ok()?;
+      if !langs.contains(&lang) {
+        langs.push(lang);
+      }
+    }
+    Some(langs)
+  }
+
+  fn check(&self) -> Result<(), String> {
+    let rules = self
+      .rules
+      .iter()
+      .map(|rule| rule_config(rule.clone()))
+      .collect::<Result<Vec<_>, _>>()?;
+    if let Some(example) = &self.example {
+      if let Some(expected) = highlighted_lines(&example.info) {
+        let matches = find_matches(example.body.clone(), &rules, &Default::default()).map_err(|e| e.to_string())?;
+        let (mut actual, mut first_lines) = (BTreeSet::new(), BTreeSet::new());
+        for m in matches.values().flatten() {
+          let (start, _, end, end_column) = m.node.range;
+          let end = if end > start && end_column == 0 { end - 1 } else { end };
+          actual.extend(start..=end);
+          first_lines.insert(start);
+        }
+        // some examples only highlight the first line of each match
+        if actual != expected && first_lines != expected {
+          let lines = |set: &BTreeSet<usize>| set.iter().map(|l| l + 1).collect::<Vec<_>>();
+          return Err(format!(
+            "expected matches on lines {:?}, found {:?}",
+            lines(&expected),
+            lines(&actual)
+          ));
+        }
+      }
+    }
+    let has_fix = self.rules.iter().any(|r| r.get("fix").is_some());
+    if let (true, Some(diff)) = (has_fix, &self.diff) {
+      let (before, after) = split_diff(&diff.body);
+      let fixed = apply_fixes(before, &rules).map_err(|_| "failed to apply fixes".to_string())?;
+      if normalize_code(&fixed) != normalize_code(&after) {
+        return Err(format!("fixed code differs from the diff:\n{}", fixed));
+      }
+    }
+    Ok(())
+  }
+}
+
+/// same steps as `parse_rule_config`, keeping the error as a string
+/// since a `JsError` cannot be created without a js host
+fn rule_config(value: Value) -> Result<RuleConfig<WasmLang>, String> {
+  let config: SerializableRuleConfig<WasmLang> =
+    serde_json::from_value(value.clone()).map_err(|e| e.to_string())?;
+  let grammar = Grammar::of(config.language).map_err(|e| e.to_string())?;
+  grammar.validate(&value, &Default::default()).map_err(|e| e.to_string())?;
+  RuleConfig::try_from(config, &Default::default()).map_err(|e| e.to_string())
+}
+
+fn catalog_files() -> Vec<PathBuf> {
+  let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("website/catalog");
+  let mut files = vec![];
+  for dir in fs::read_dir(root).expect("catalog should exist").flatten() {
+    let Ok(entries) = fs::read_dir(dir.path()) else {
+      continue;
+    };
+    for file in entries.flatten() {
+      let path = file.path();
+      let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+      if name.ends_with(".md") && name != "index.md" {
+        files.push(path);
+      }
+    }
+  }
+  files.sort();
+  files
+}
+
+/// entries whose documentation disagrees with ast-grep, checked to still fail
+const KNOWN_ISSUES: &[(&str, &str)] = &[
+  ("python/refactor-pytest-fixtures", "the rule is split across snippets without a YAML section"),
+  ("python/remove-async-await", "the YAML declares `rewriters` as a map instead of a list"),
+  ("python/rewrite-sqlalchemy-mapped-column", "the example highlights line 8 instead of lines 9-10"),
+  ("rust/redundant-unsafe-function", "the example highlights the lines above three of the matches"),
+  ("rust/rewrite-indoc-macro", "the diff leaves `.foo {` out of the original code"),
+  ("tsx/avoid-jsx-short-circuit", "the diff adds spaces the fix does not"),
+  ("tsx/avoid-nested-links", "the example highlights the whole component instead of the outer link"),
+  ("typescript/find-import-identifiers", "the example highlights only its last line"),
+  ("typescript/find-import-usage", "the example does not highlight the imported identifier itself"),
+];
+
+#[test]
+fn test_catalog_examples() {
+  let _lock = use_langs(&[]);
+  let (mut checked, mut failures) = (0, vec![]);
+  for path in catalog_files() {
+    let Some(entry) = CatalogEntry::read(&path) else {
+      continue;
+    };
+    let known = KNOWN_ISSUES.iter().any(|(name, _)| *name == entry.name);
+    let result = match entry.languages() {
+      Some(langs) if !entry.rules.is_empty() => {
+        // the examples of languages without a bundled grammar cannot be parsed
+        if langs.iter().any(|l| WasmLang::set_native(*l).is_err()) {
+          continue;
+        }
+        // the first rule language hosts the example
+        WasmLang::set_native(langs[0]).expect("grammar should load");
+        checked += 1;
+        entry.check()
+      }
+      _ => Err("no rule found".to_string()),
+    };
+    match result {
+      Ok(()) if known => failures.push(format!("{}: passes, remove it from KNOWN_ISSUES", entry.name)),
+      Err(e) if !known => failures.push(format!("{}: {}", entry.name, e)),
+      _ => {}
+    }
+  }
+  assert!(checked > 0, "no catalog entry was checked");
+  assert!(failures.is_empty(), "catalog entries failed:\n{}", failures.join("\n"));
+}
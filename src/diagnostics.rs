@@ -0,0 +1,95 @@
+use crate::dump_tree::Pos;
+use crate::ts::SyntaxNode;
+use crate::wasm_lang::node_children;
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SyntaxErrorKind {
+  Error,
+  Missing,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyntaxDiagnostic {
+  kind: SyntaxErrorKind,
+  message: String,
+  start: Pos,
+  end: Pos,
+  range: (u32, u32),
+  /// the node kind tree-sitter inserted for MISSING nodes
+  expected: Option<String>,
+  /// kind of the closest valid ancestor
+  parent: Option<String>,
+  /// source line where the error starts
+  context: String,
+}
+
+/// text the tree was reported against, offsets are in UTF-16 code units
+struct Source<'a> {
+  lines: Vec<&'a str>,
+  units: Vec<u16>,
+}
+
+impl Source<'_> {
+  fn text(&self, node: &SyntaxNode) -> String {
+    let start = node.start_index() as usize;
+    let end = (node.end_index() as usize).min(self.units.len());
+    String::from_utf16_lossy(self.units.get(start..end).unwrap_or_default())
+  }
+}
+
+/// collect all ERROR and MISSING nodes, skipping subtrees without errors.
+/// `src` may differ from the parsed text as long as positions are the same,
+/// e.g. a pattern before its metavariables are replaced by expando chars
+pub fn syntax_diagnostics(root: &SyntaxNode, src: &str) -> Vec<SyntaxDiagnostic> {
+  let src = Source {
+    lines: src.lines().collect(),
+    units: src.encode_utf16().collect(),
+  };
+  let mut ret = vec![];
+  visit(root.clone(), &src, &mut ret);
+  ret
+}
+
+fn visit(node: SyntaxNode, src: &Source, ret: &mut Vec<SyntaxDiagnostic>) {
+  if node.is_missing() {
+    let expected = format!("{}", node.type_());
+    let message = format!("missing `{}`", expected);
+    ret.push(diagnose(&node, SyntaxErrorKind::Missing, message, Some(expected), src));
+  } else if node.is_error() {
+    let message = format!("unexpected `{}`", src.text(&node).trim());
+    ret.push(diagnose(&node, SyntaxErrorKind::Error, message, None, src));
+  } else if node.has_error() {
+    for child in node_children(&node) {
+      visit(child, src, ret);
+    }
+  }
+}
+
+fn diagnose(
+  node: &SyntaxNode,
+  kind: SyntaxErrorKind,
+  message: String,
+  expected: Option<String>,
+  src: &Source,
+) -> SyntaxDiagnostic {
+  let start = node.start_position();
+  let context = src.lines.get(start.row() as usize).unwrap_or(&"").to_string();
+  let mut parent = node.parent();
+  while let Some(p) = parent.as_ref().filter(|p| p.is_error()) {
+    parent = p.parent();
+  }
+  let parent = parent.map(|p| format!("{}", p.type_()));
+  SyntaxDiagnostic {
+    kind,
+    message,
+    start: start.into(),
+    end: node.end_position().into(),
+    range: (node.start_index(), node.end_index()),
+    expected,
+    parent,
+    context,
+  }
+}
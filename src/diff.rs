@@ -0,0 +1,362 @@
+use crate::{apply_edits, RuleEdit};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DiffOptions {
+  /// unchanged lines around each change
+  pub context: usize,
+  /// written as `--- <oldPath>`, defaults to `newPath`
+  pub old_path: Option<String>,
+  /// written as `+++ <newPath>`, defaults to `oldPath`
+  pub new_path: Option<String>,
+}
+
+impl Default for DiffOptions {
+  fn default() -> Self {
+    Self {
+      context: 3,
+      old_path: None,
+      new_path: None,
+    }
+  }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DiffLineKind {
+  Context,
+  Removed,
+  Added,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiffLine {
+  kind: DiffLineKind,
+  text: String,
+  /// 1-based line in the original source, None for added lines
+  old_line: Option<usize>,
+  /// 1-based line in the fixed source, None for removed lines
+  new_line: Option<usize>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiffHunk {
+  old_start: usize,
+  old_lines: usize,
+  new_start: usize,
+  new_lines: usize,
+  /// rules whose fixes are in the hunk
+  rule_ids: Vec<String>,
+  lines: Vec<DiffLine>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FixDiff {
+  /// unified diff, empty if nothing is fixed
+  diff: String,
+  hunks: Vec<DiffHunk>,
+  fixed: String,
+}
+
+struct LineIndex {
+  starts: Vec<usize>,
+  len: usize,
+  trailing_newline: bool,
+}
+
+impl LineIndex {
+  fn new(text: &[char]) -> Self {
+    let mut starts = vec![0];
+    starts.extend(text.iter().enumerate().filter(|(_, c)| **c == '\n').map(|(i, _)| i + 1));
+    let trailing_newline = text.last() == Some(&'\n');
+    if trailing_newline || text.is_empty() {
+      starts.pop();
+    }
+    Self {
+      starts,
+      len: text.len(),
+      trailing_newline,
+    }
+  }
+
+  fn count(&self) -> usize {
+    self.starts.len()
+  }
+
+  /// the line containing offset, or `count` for the end of a newline terminated text
+  fn line_of(&self, offset: usize) -> usize {
+    if offset >= self.len && (self.trailing_newline || self.len == 0) {
+      return self.count();
+    }
+    self.starts.partition_point(|&s| s <= offset).saturating_sub(1)
+  }
+
+  /// lines touched by the char range
+  fn lines_of(&self, start: usize, end: usize) -> (usize, usize) {
+    let first = self.line_of(start);
+    let last = (self.line_of(end) + 1).min(self.count());
+    (first, last.max(first))
+  }
+
+  fn text(&self, chars: &[char], line: usize) -> String {
+    let start = self.starts[line];
+    let end = match self.starts.get(line + 1) {
+      Some(next) => next - 1,
+      None if self.trailing_newline => self.len - 1,
+      None => self.len,
+    };
+    chars[start..end].iter().collect()
+  }
+
+  fn missing_newline(&self, line: usize) -> bool {
+    line + 1 == self.count() && !self.trailing_newline
+  }
+}
+
+/// changed line ranges, old and new, both half open
+struct Change {
+  old: (usize, usize),
+  new: (usize, usize),
+  ids: Vec<String>,
+}
+
+fn add_id(ids: &mut Vec<String>, id: &str) {
+  if !ids.iter().any(|i| i == id) {
+    ids.push(id.to_string());
+  }
+}
+
+struct Side<'a> {
+  chars: &'a [char],
+  index: LineIndex,
+}
+
+impl<'a> Side<'a> {
+  fn new(chars: &'a [char]) -> Self {
+    Self {
+      chars,
+      index: LineIndex::new(chars),
+    }
+  }
+  fn line(&self, line: usize) -> String {
+    self.index.text(self.chars, line)
+  }
+  /// a line that loses its newline is still a change
+  fn same_line(&self, line: usize, other: &Side, other_line: usize) -> bool {
+    self.line(line) == other.line(other_line)
+      && self.index.missing_newline(line) == other.index.missing_newline(other_line)
+  }
+}
+
+fn collect_changes(old: &Side, new: &Side, edits: &[RuleEdit]) -> Vec<Change> {
+  let mut changes: Vec<Change> = vec![];
+  let mut shift = 0isize;
+  for (id, edit) in edits {
+    let start = edit.position;
+    let new_start = (start as isize + shift) as usize;
+    let inserted = edit.inserted_text.len();
+    let change = Change {
+      old: old.index.lines_of(start, start + edit.deleted_length),
+      new: new.index.lines_of(new_start, new_start + inserted),
+      ids: vec![id.clone()],
+    };
+    shift += inserted as isize - edit.deleted_length as isize;
+    match changes.last_mut() {
+      Some(prev) if change.old.0 < prev.old.1 || change.new.0 < prev.new.1 => {
+        prev.old.1 = prev.old.1.max(change.old.1);
+        prev.new.1 = prev.new.1.max(change.new.1);
+        add_id(&mut prev.ids, id);
+      }
+      _ => changes.push(change),
+    }
+  }
+  // an edit rarely rewrites its whole lines, drop the lines that stay the same
+  changes.retain_mut(|c| {
+    let overlaps = |c: &Change| c.old.0 < c.old.1 && c.new.0 < c.new.1;
+    while overlaps(c) && old.same_line(c.old.0, new, c.new.0) {
+      c.old.0 += 1;
+      c.new.0 += 1;
+    }
+    while overlaps(c) && old.same_line(c.old.1 - 1, new, c.new.1 - 1) {
+      c.old.1 -= 1;
+      c.new.1 -= 1;
+    }
+    c.old.0 < c.old.1 || c.new.0 < c.new.1
+  });
+  changes
+}
+
+fn build_hunk(old: &Side, new: &Side, changes: &[Change], context: usize) -> DiffHunk {
+  let (first, last) = (&changes[0], &changes[changes.len() - 1]);
+  let old_start = first.old.0.saturating_sub(context);
+  let old_end = (last.old.1 + context).min(old.index.count());
+  let new_start = first.new.0 - (first.old.0 - old_start);
+  let mut lines = vec![];
+  let mut rule_ids = vec![];
+  let (mut old_line, mut new_line) = (old_start, new_start);
+  // a trailing None emits the context after the last change
+  for change in changes.iter().map(Some).chain(std::iter::once(None)) {
+    let until = change.map_or(old_end, |c| c.old.0);
+    while old_line < until {
+      lines.push(DiffLine {
+        kind: DiffLineKind::Context,
+        text: old.line(old_line),
+        old_line: Some(old_line + 1),
+        new_line: Some(new_line + 1),
+      });
+      old_line += 1;
+      new_line += 1;
+    }
+    let Some(change) = change else {
+      break;
+    };
+    for line in change.old.0..change.old.1 {
+      lines.push(DiffLine {
+        kind: DiffLineKind::Removed,
+        text: old.line(line),
+        old_line: Some(line + 1),
+        new_line: None,
+      });
+    }
+    for line in change.new.0..change.new.1 {
+      lines.push(DiffLine {
+        kind: DiffLineKind::Added,
+        text: new.line(line),
+        old_line: None,
+        new_line: Some(line + 1),
+      });
+    }
+    for id in &change.ids {
+      add_id(&mut rule_ids, id);
+    }
+    old_line = change.old.1;
+    new_line = change.new.1;
+  }
+  let old_lines = old_end - old_start;
+  let new_lines = lines.iter().filter(|l| l.kind != DiffLineKind::Removed).count();
+  DiffHunk {
+    // unified diffs give the line before an empty range
+    old_start: if old_lines == 0 { old_start } else { old_start + 1 },
+    old_lines,
+    new_start: if new_lines == 0 { new_start } else { new_start + 1 },
+    new_lines,
+    rule_ids,
+    lines,
+  }
+}
+
+fn write_hunk(hunk: &DiffHunk, old: &Side, new: &Side, out: &mut String) {
+  out.push_str(&format!(
+    "@@ -{},{} +{},{} @@ {}\n",
+    hunk.old_start,
+    hunk.old_lines,
+    hunk.new_start,
+    hunk.new_lines,
+    hunk.rule_ids.join(", ")
+  ));
+  for line in &hunk.lines {
+    let (prefix, index, line_number) = match line.kind {
+      DiffLineKind::Context => (' ', &old.index, line.old_line),
+      DiffLineKind::Removed => ('-', &old.index, line.old_line),
+      DiffLineKind::Added => ('+', &new.index, line.new_line),
+    };
+    out.push(prefix);
+    out.push_str(&line.text);
+    out.push('\n');
+    if line_number.map_or(false, |l| index.missing_newline(l - 1)) {
+      out.push_str("\\ No newline at end of file\n");
+    }
+  }
+}
+
+impl FixDiff {
+  pub fn new(src: &str, edits: &[RuleEdit], options: &DiffOptions) -> Self {
+    let old_chars: Vec<_> = src.chars().collect();
+    let new_chars = apply_edits(&old_chars, edits);
+    let (old, new) = (Side::new(&old_chars), Side::new(&new_chars));
+    let changes = collect_changes(&old, &new, edits);
+    let mut hunks = vec![];
+    let mut group_start = 0;
+    for i in 1..=changes.len() {
+      let joined = changes
+        .get(i)
+        .map_or(false, |c| c.old.0 - changes[i - 1].old.1 <= 2 * options.context);
+      if !joined {
+        hunks.push(build_hunk(&old, &new, &changes[group_start..i], options.context));
+        group_start = i;
+      }
+    }
+    let mut diff = String::new();
+    if !hunks.is_empty() {
+      let old_path = options.old_path.as_ref().or(options.new_path.as_ref());
+      let new_path = options.new_path.as_ref().or(options.old_path.as_ref());
+      if let (Some(old_path), Some(new_path)) = (old_path, new_path) {
+        diff.push_str(&format!("--- {}\n+++ {}\n", old_path, new_path));
+      }
+    }
+    for hunk in &hunks {
+      write_hunk(hunk, &old, &new, &mut diff);
+    }
+    Self {
+      diff,
+      hunks,
+      fixed: new_chars.into_iter().collect(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use ast_grep_core::source::Edit;
+
+  fn edit(id: &str, position: usize, deleted_length: usize, text: &str) -> RuleEdit {
+    let edit = Edit {
+      position,
+      deleted_length,
+      inserted_text: text.chars().collect(),
+    };
+    (id.to_string(), edit)
+  }
+
+  fn options(context: usize) -> DiffOptions {
+    DiffOptions {
+      context,
+      old_path: Some("a/test.js".into()),
+      new_path: Some("b/test.js".into()),
+    }
+  }
+
+  #[test]
+  fn test_single_hunk() {
+    let src = "a\nfoo(1)\nb\n";
+    let diff = FixDiff::new(src, &[edit("rename", 2, 3, "bar")], &options(1));
+    let expected = "--- a/test.js\n+++ b/test.js\n@@ -1,3 +1,3 @@ rename\n a\n-foo(1)\n+bar(1)\n b\n";
+    assert_eq!(diff.diff, expected);
+    assert_eq!(diff.fixed, "a\nbar(1)\nb\n");
+    assert_eq!(diff.hunks[0].rule_ids, ["rename"]);
+  }
+
+  #[test]
+  fn test_separate_hunks() {
+    let src = "x\n1\n2\n3\n4\n5\nx\n";
+    let edits = [edit("first", 0, 1, "y"), edit("second", 12, 1, "z")];
+    let diff = FixDiff::new(src, &edits, &options(1));
+    assert_eq!(diff.hunks.len(), 2);
+    assert_eq!(diff.hunks[1].rule_ids, ["second"]);
+    assert!(diff.diff.contains("@@ -6,2 +6,2 @@ second\n 5\n-x\n+z\n"));
+  }
+
+  #[test]
+  fn test_deleted_line_without_newline() {
+    let src = "keep\ndrop";
+    let diff = FixDiff::new(src, &[edit("drop", 4, 5, "")], &options(0));
+    assert_eq!(diff.fixed, "keep");
+    let expected = "@@ -1,2 +1,1 @@ drop\n-keep\n-drop\n\\ No newline at end of file\n+keep\n\\ No newline at end of file\n";
+    assert!(diff.diff.ends_with(expected));
+  }
+}
@@ -1,19 +1,32 @@
 use serde::{Deserialize, Serialize};
+use crate::diagnostics::{syntax_diagnostics, SyntaxDiagnostic};
+use crate::render::{GraphNode, GraphOptions, NodeStyle};
 use crate::wasm_lang::{WasmDoc, WasmLang};
 use ast_grep_core::{
   matcher::PatternNode, AstGrep, Language, Node, Pattern
 };
-use wasm_bindgen::prelude::JsError;
-use web_tree_sitter_sg::{Point, TreeCursor};
+use wasm_bindgen::prelude::*;
+use crate::ts::{Point, SyntaxNode, TreeCursor};
 
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct DumpNode {
+  /// same as the `id` of matches in the same source
+  id: usize,
   field: Option<String>,
   kind: String,
   start: Pos,
   end: Pos,
+  /// start and end offsets
+  range: (u32, u32),
   is_named: bool,
+  is_error: bool,
+  is_missing: bool,
+  is_extra: bool,
+  /// source text of leaf nodes
+  text: Option<String>,
+  /// children are omitted due to max depth, expand them by `nodeId`
+  collapsed: bool,
   children: Vec<DumpNode>,
 }
 
@@ -33,37 +46,162 @@ impl From<Point> for Pos {
   }
 }
 
-pub fn dump_one_node(cursor: &mut TreeCursor, target: &mut Vec<DumpNode>) {
+impl From<&Pos> for Point {
+  fn from(pos: &Pos) -> Self {
+    Point::new(pos.row, pos.column)
+  }
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DumpOptions {
+  /// dump the smallest node covering the range, end defaults to start
+  start: Option<Pos>,
+  end: Option<Pos>,
+  /// same as start/end but in offsets
+  start_index: Option<u32>,
+  end_index: Option<u32>,
+  max_depth: Option<usize>,
+  named_only: bool,
+  /// expand a node returned by a previous dump of the same source
+  node_id: Option<usize>,
+}
+
+pub fn dump_ast(src: String, options: &DumpOptions) -> Result<DumpNode, JsError> {
+  let lang = WasmLang::get_current();
+  let root = AstGrep::doc(WasmDoc::cached(src, lang)?);
+  let node = select_root(&root, options)?;
+  let mut cursor = node.walk();
+  let mut target = vec![];
+  dump_one_node(&mut cursor, &mut target, options, 0);
+  target.pop().ok_or_else(|| JsError::new("found empty node"))
+}
+
+fn select_root(root: &AstGrep<WasmDoc>, options: &DumpOptions) -> Result<SyntaxNode, JsError> {
+  if let Some(id) = options.node_id {
+    let found = root.root().dfs().find(|n| n.node_id() == id);
+    let node = found.ok_or_else(|| JsError::new("node not found, source may have changed"))?;
+    return Ok(node.get_inner_node().0);
+  }
+  let node = root.root().get_inner_node().0;
+  let named = options.named_only;
+  let found = if let Some(start) = options.start_index {
+    let end = options.end_index.unwrap_or(start);
+    if named {
+      node.named_descendant_for_index_range(start, end)
+    } else {
+      node.descendant_for_index_range(start, end)
+    }
+  } else if let Some(start) = &options.start {
+    let start = Point::from(start);
+    let end = options.end.as_ref().map_or_else(|| start.clone(), Point::from);
+    if named {
+      node.named_descendant_for_position_range(&start, &end)
+    } else {
+      node.descendant_for_position_range(&start, &end)
+    }
+  } else {
+    None
+  };
+  Ok(found.unwrap_or(node))
+}
+
+fn dump_one_node(cursor: &mut TreeCursor, target: &mut Vec<DumpNode>, options: &DumpOptions, depth: usize) {
   let node = cursor.current_node();
-  let kind = if node.is_missing() {
-    format!("MISSING {}", node.type_())
+  let skipped = options.named_only && !node.is_named() && depth > 0;
+  let child_count = if options.named_only {
+    node.named_child_count()
   } else {
-    format!("{}", node.type_())
+    node.child_count()
   };
-  let start = node.start_position().into();
-  let end = node.end_position().into();
-  let field = cursor.current_field_name().map(|c| format!("{}", c));
+  let collapsed = child_count > 0 && options.max_depth.map_or(false, |max| depth >= max);
   let mut children = vec![];
-  if cursor.goto_first_child() {
-    dump_nodes(cursor, &mut children);
+  if !skipped && !collapsed && cursor.goto_first_child() {
+    loop {
+      dump_one_node(cursor, &mut children, options, depth + 1);
+      if !cursor.goto_next_sibling() {
+        break;
+      }
+    }
     cursor.goto_parent();
   }
+  if skipped {
+    return;
+  }
+  let kind = if node.is_missing() {
+    format!("MISSING {}", node.type_())
+  } else {
+    format!("{}", node.type_())
+  };
+  let text = if node.child_count() == 0 {
+    Some(node.text().into())
+  } else {
+    None
+  };
   target.push(DumpNode {
-    field,
+    id: node.id() as usize,
+    field: cursor.current_field_name().map(|c| format!("{}", c)),
     kind,
-    start,
-    end,
+    start: node.start_position().into(),
+    end: node.end_position().into(),
+    range: (node.start_index(), node.end_index()),
     children,
+    collapsed,
+    text,
     is_named: node.is_named(),
-  })
+    is_error: node.is_error(),
+    is_missing: node.is_missing(),
+    is_extra: node.is_extra(),
+  });
+}
+
+impl DumpNode {
+  pub fn to_graph(&self, options: &GraphOptions, depth: usize) -> GraphNode {
+    let style = if self.is_error {
+      NodeStyle::Error
+    } else if self.is_missing {
+      NodeStyle::Missing
+    } else if options.is_matched(self.range) {
+      NodeStyle::Matched
+    } else if !self.is_named {
+      NodeStyle::Anonymous
+    } else {
+      NodeStyle::Plain
+    };
+    let mut node = GraphNode::new(&self.kind, self.text.as_deref(), self.field.clone(), style);
+    if !options.is_too_deep(depth) {
+      node.children = self
+        .children
+        .iter()
+        .filter(|c| !options.named_only || c.is_named)
+        .map(|c| c.to_graph(options, depth + 1))
+        .collect();
+    }
+    node
+  }
 }
 
-fn dump_nodes(cursor: &mut TreeCursor, target: &mut Vec<DumpNode>) {
-  loop {
-    dump_one_node(cursor, target);
-    if !cursor.goto_next_sibling() {
-      break;
+impl PatternTree {
+  pub fn to_graph(&self, options: &GraphOptions, depth: usize) -> GraphNode {
+    let style = match &self.pattern {
+      Some(PatternKind::MetaVar) => NodeStyle::MetaVar,
+      _ if self.kind == "ERROR" => NodeStyle::Error,
+      _ if self.kind.starts_with("MISSING ") => NodeStyle::Missing,
+      // nodes in the pattern, as opposed to the surrounding context
+      Some(_) => NodeStyle::Matched,
+      None if !self.is_named => NodeStyle::Anonymous,
+      None => NodeStyle::Plain,
+    };
+    let mut node = GraphNode::new(&self.kind, self.text.as_deref(), None, style);
+    if !options.is_too_deep(depth) {
+      node.children = self
+        .children
+        .iter()
+        .filter(|c| !options.named_only || c.is_named)
+        .map(|c| c.to_graph(options, depth + 1))
+        .collect();
     }
+    node
   }
 }
 
@@ -72,21 +210,139 @@ pub fn dump_pattern(query: String, selector: Option<String>) -> Result<PatternTr
   let processed = lang.pre_process_pattern(&query);
   let doc = WasmDoc::try_new(processed.to_string(), lang)?;
   let root = AstGrep::doc(doc);
-  let pattern = if let Some(sel) = selector {
-    Pattern::contextual(&query, &sel, lang)?
-  } else {
-    Pattern::try_new(&query, lang)?
+  let pattern = match &selector {
+    Some(sel) => Pattern::contextual(&query, sel, lang).map_err(|e| {
+      let kinds = present_kinds(root.root()).join(", ");
+      JsError::new(&format!("{}\nSelector `{}` is not found in the pattern. Kinds in the pattern: {}", e, sel, kinds))
+    })?,
+    None => match Pattern::try_new(&query, lang) {
+      Ok(pattern) => pattern,
+      Err(e) => {
+        let candidates: Vec<_> = root.root().children().filter(|n| n.is_named()).collect();
+        if candidates.len() < 2 {
+          return Err(e.into());
+        }
+        return Ok(dump_multi_root(root.root(), candidates, &query));
+      }
+    },
+  };
+  let (ret, _) = dump_found_pattern(root.root(), &pattern, &query).ok_or_else(|| {
+    let kinds = present_kinds(root.root()).join(", ");
+    JsError::new(&format!("pattern node not found. Kinds in the pattern: {}", kinds))
+  })?;
+  Ok(ret)
+}
+
+/// dump the tree with the node matched by pattern, also returning the node id.
+/// `src` is the query before expando replacement, which keeps positions intact,
+/// so diagnostics show `$A` instead of the expando char
+fn dump_found_pattern(root: Node<WasmDoc>, pattern: &Pattern, src: &str) -> Option<(PatternTree, usize)> {
+  let found = root.find(pattern)?;
+  let found_id = found.node_id();
+  let mut ret = dump_context_tree(root.clone(), &|n: &Node<WasmDoc>| {
+    (n.node_id() == found_id).then(|| dump_pattern_impl(n.clone(), &pattern.node))
+  });
+  ret.root_kinds = vec![found.kind().to_string()];
+  ret.diagnostics = syntax_diagnostics(&root.get_inner_node().0, src);
+  Some((ret, found_id))
+}
+
+/// smallest named node in the subtree covering the points, end defaults to start
+pub fn find_covering_node<'r>(
+  node: Node<'r, WasmDoc>,
+  start: &Pos,
+  end: Option<&Pos>,
+) -> Option<Node<'r, WasmDoc>> {
+  let start = Point::from(start);
+  let end = end.map_or_else(|| start.clone(), Point::from);
+  let covering = node.get_inner_node().0.named_descendant_for_position_range(&start, &end)?;
+  let id = covering.id() as usize;
+  node.dfs().find(|n| n.node_id() == id)
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SelectorRange {
+  start: Pos,
+  end: Option<Pos>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SelectorCandidate {
+  selector: String,
+  /// false if the selector picks another node of the same kind earlier in the context
+  exact: bool,
+  tree: PatternTree,
+}
+
+/// candidate selectors for named nodes covering the range, from innermost to outermost
+pub fn suggest_selectors(query: String, range: SelectorRange) -> Result<Vec<SelectorCandidate>, JsError> {
+  let lang = WasmLang::get_current();
+  // expando replacement keeps char positions intact
+  let processed = lang.pre_process_pattern(&query);
+  let doc = WasmDoc::try_new(processed.to_string(), lang)?;
+  let root = AstGrep::doc(doc);
+  let root_id = root.root().node_id();
+  let Some(covering) = find_covering_node(root.root(), &range.start, range.end.as_ref()) else {
+    return Ok(vec![]);
   };
-  let found = root.root().find(&pattern).ok_or_else(|| JsError::new("pattern node not found"))?;
-  let ret = dump_pattern_tree(root.root(), found.node_id(), &pattern.node);
+  let chain = std::iter::once(covering.clone())
+    .chain(covering.ancestors())
+    .filter(|n| n.is_named() && n.node_id() != root_id);
+  let mut ret: Vec<SelectorCandidate> = vec![];
+  for node in chain {
+    let selector = node.kind().to_string();
+    if ret.iter().any(|c| c.selector == selector) {
+      continue;
+    }
+    let Ok(pattern) = Pattern::contextual(&query, &selector, lang) else {
+      continue;
+    };
+    let Some((tree, found_id)) = dump_found_pattern(root.root(), &pattern, &query) else {
+      continue;
+    };
+    ret.push(SelectorCandidate {
+      selector,
+      exact: found_id == node.node_id(),
+      tree,
+    });
+  }
   Ok(ret)
 }
 
-fn dump_pattern_tree(node: Node<WasmDoc>, node_id: usize, pattern: &PatternNode) -> PatternTree {
-  if node.node_id() == node_id {
-    return dump_pattern_impl(node, pattern)
+/// dump every top level node as a pattern when the query has no single root,
+/// e.g. statement sequences like `$A; $B`
+fn dump_multi_root(root: Node<WasmDoc>, candidates: Vec<Node<WasmDoc>>, src: &str) -> PatternTree {
+  let ids: Vec<_> = candidates.iter().map(|n| n.node_id()).collect();
+  let mut ret = dump_context_tree(root.clone(), &|n: &Node<WasmDoc>| {
+    ids.contains(&n.node_id()).then(|| dump_inferred_pattern(n.clone()))
+  });
+  ret.root_kinds = candidates.iter().map(|n| n.kind().to_string()).collect();
+  ret.diagnostics = syntax_diagnostics(&root.get_inner_node().0, src);
+  ret
+}
+
+/// distinct named kinds in the order they appear
+fn present_kinds(root: Node<WasmDoc>) -> Vec<String> {
+  let mut kinds: Vec<String> = vec![];
+  for node in root.dfs().filter(|n| n.is_named()) {
+    let kind = node.kind();
+    if !kinds.iter().any(|k| *k == kind) {
+      kinds.push(kind.to_string());
+    }
+  }
+  kinds
+}
+
+fn dump_context_tree(
+  node: Node<WasmDoc>,
+  dump_root: &impl Fn(&Node<WasmDoc>) -> Option<PatternTree>,
+) -> PatternTree {
+  if let Some(tree) = dump_root(&node) {
+    return tree;
   }
-  let children: Vec<_> = node.children().map(|n| dump_pattern_tree(n, node_id, pattern)).collect();
+  let children: Vec<_> = node.children().map(|n| dump_context_tree(n, dump_root)).collect();
   let ts = node.get_inner_node().0;
   let text = if children.is_empty() {
     Some(node.text().into())
@@ -106,6 +362,35 @@ fn dump_pattern_tree(node: Node<WasmDoc>, node_id: usize, pattern: &PatternNode)
     children,
     text,
     pattern: None,
+    root_kinds: vec![],
+    diagnostics: vec![],
+  }
+}
+
+/// mirror how a pattern is built from a node without a compiled `Pattern`
+fn dump_inferred_pattern(node: Node<WasmDoc>) -> PatternTree {
+  let ts = node.get_inner_node().0;
+  let lang = node.lang();
+  let text = node.text().to_string();
+  let (pattern, text, children) = if lang.extract_meta_var(&text).is_some() {
+    let text = text.replace(lang.expando_char(), "$");
+    (PatternKind::MetaVar, Some(text), vec![])
+  } else if node.is_leaf() {
+    (PatternKind::Terminal, Some(text), vec![])
+  } else {
+    let children = node.children().map(dump_inferred_pattern).collect();
+    (PatternKind::Internal, None, children)
+  };
+  PatternTree {
+    kind: node.kind().to_string(),
+    start: ts.start_position().into(),
+    end: ts.end_position().into(),
+    is_named: node.is_named(),
+    children,
+    text,
+    pattern: Some(pattern),
+    root_kinds: vec![],
+    diagnostics: vec![],
   }
 }
 
@@ -131,6 +416,8 @@ fn dump_pattern_impl(node: Node<WasmDoc>, pattern: &PatternNode) -> PatternTree
         children: vec![],
         text: Some(text),
         pattern: Some(PatternKind::MetaVar),
+        root_kinds: vec![],
+        diagnostics: vec![],
       }
     }
     PN::Terminal { is_named, .. } => {
@@ -142,6 +429,8 @@ fn dump_pattern_impl(node: Node<WasmDoc>, pattern: &PatternNode) -> PatternTree
         children: vec![],
         text: Some(node.text().into()),
         pattern: Some(PatternKind::Terminal),
+        root_kinds: vec![],
+        diagnostics: vec![],
       }
     }
     PN::Internal { children, .. } => {
@@ -156,6 +445,8 @@ fn dump_pattern_impl(node: Node<WasmDoc>, pattern: &PatternNode) -> PatternTree
         children,
         text: None,
         pattern: Some(PatternKind::Internal),
+        root_kinds: vec![],
+        diagnostics: vec![],
       }
     }
   }
@@ -180,4 +471,10 @@ pub struct PatternTree {
   children: Vec<PatternTree>,
   text: Option<String>,
   pattern: Option<PatternKind>,
+  /// kinds of the pattern root nodes, only reported on the root
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  root_kinds: Vec<String>,
+  /// syntax errors in the pattern, only reported on the root
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  diagnostics: Vec<SyntaxDiagnostic>,
 }
\ No newline at end of file
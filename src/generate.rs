@@ -0,0 +1,431 @@
+use crate::dump_tree::{find_covering_node, Pos};
+use crate::wasm_lang::{SgWasmError, WasmDoc, WasmLang};
+use ast_grep_config::{CombinedScan, RuleConfig, SerializableRuleConfig};
+use ast_grep_core::{AstGrep, Node as SgNode, Pattern};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::ops::Range;
+use wasm_bindgen::prelude::JsError;
+
+type Node<'a> = SgNode<'a, WasmDoc>;
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetaVarSelection {
+  /// e.g. `$A` or `$$$ARGS`
+  name: String,
+  start: Pos,
+  end: Option<Pos>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Selection {
+  start: Pos,
+  end: Option<Pos>,
+  #[serde(default)]
+  meta_vars: Vec<MetaVarSelection>,
+  id: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeneratedRule {
+  pub kind: String,
+  pub pattern: String,
+  /// set if the pattern needs surrounding code to parse
+  pub context: Option<String>,
+  pub selector: Option<String>,
+  pub yaml: String,
+}
+
+impl GeneratedRule {
+  pub fn new(id: &str, lang: WasmLang, kind: String, pattern: String, context: Option<String>) -> Self {
+    let (rule, selector) = match &context {
+      Some(context) => (json!({ "pattern": { "context": context, "selector": kind } }), Some(kind.clone())),
+      None => (json!({ "pattern": pattern }), None),
+    };
+    let config = json!({ "id": id, "language": lang.to_string(), "rule": rule });
+    let yaml = to_yaml(&config, &["id", "language", "rule"]);
+    Self {
+      kind,
+      pattern,
+      context,
+      selector,
+      yaml,
+    }
+  }
+}
+
+const DEFAULT_ID: &str = "generated-rule";
+
+/// build a pattern for the smallest node enclosing the selection,
+/// falling back to a contextual pattern if the snippet does not parse alone
+pub fn pattern_from_selection(src: String, selection: Selection) -> Result<GeneratedRule, JsError> {
+  let lang = WasmLang::get_current();
+  let doc = WasmDoc::try_new(src, lang)?;
+  let root = AstGrep::doc(doc);
+  let selected = find_covering_node(root.root(), &selection.start, selection.end.as_ref())
+    .ok_or_else(|| JsError::new("no node found at the selection"))?;
+  let mut vars = vec![];
+  for var in &selection.meta_vars {
+    let node = find_covering_node(selected.clone(), &var.start, var.end.as_ref())
+      .ok_or_else(|| JsError::new(&format!("no node found for {}", var.name)))?;
+    let name = if var.name.starts_with('$') {
+      var.name.clone()
+    } else {
+      format!("${}", var.name)
+    };
+    vars.push((meta_var_range(&node, &name), name));
+  }
+  let id = selection.id.as_deref().unwrap_or(DEFAULT_ID);
+  let kind = selected.kind().to_string();
+  let pattern = replace_ranges(&selected, &vars);
+  if Pattern::try_new(&pattern, lang).map_or(false, |p| selected.matches(&p)) {
+    return Ok(GeneratedRule::new(id, lang, kind, pattern, None));
+  }
+  for ancestor in selected.ancestors() {
+    let context = replace_ranges(&ancestor, &vars);
+    let Ok(p) = Pattern::contextual(&context, &kind, lang) else {
+      continue;
+    };
+    if selected.matches(&p) {
+      return Ok(GeneratedRule::new(id, lang, kind, pattern, Some(context)));
+    }
+  }
+  Err(JsError::new("cannot build a pattern matching the selection"))
+}
+
+/// multi meta variables like `$$$ARGS` cover the named children inside the node
+fn meta_var_range(node: &Node, name: &str) -> Range<usize> {
+  if !name.starts_with("$$$") {
+    return node.range();
+  }
+  let children: Vec<_> = node.children().filter(|n| n.is_named()).collect();
+  match (children.first(), children.last()) {
+    (Some(first), Some(last)) => first.range().start..last.range().end,
+    _ => node.range(),
+  }
+}
+
+/// node text with the absolute ranges replaced, nested and overlapping ranges are skipped
+pub fn replace_ranges(node: &Node, replacements: &[(Range<usize>, String)]) -> String {
+  let base = node.range();
+  // node ranges count UTF-16 code units
+  let mut text: Vec<_> = node.text().encode_utf16().collect();
+  let mut sorted: Vec<_> = replacements
+    .iter()
+    .filter(|(r, _)| base.start <= r.start && r.end <= base.end)
+    .collect();
+  sorted.sort_by_key(|(r, _)| std::cmp::Reverse(r.start));
+  let mut last_start = usize::MAX;
+  for (range, name) in sorted {
+    if range.end > last_start {
+      continue;
+    }
+    let range = (range.start - base.start)..(range.end - base.start);
+    last_start = range.start + base.start;
+    text.splice(range, name.encode_utf16());
+  }
+  let column = node.start_pos().column(node);
+  dedent(&String::from_utf16_lossy(&text), column)
+}
+
+/// remove the indentation of the node's first line from the following lines
+fn dedent(text: &str, column: usize) -> String {
+  let mut lines = text.lines();
+  let mut ret = lines.next().unwrap_or_default().to_string();
+  for line in lines {
+    let indent = line.len() - line.trim_start_matches([' ', '\t']).len();
+    ret.push('\n');
+    ret += &line[indent.min(column)..];
+  }
+  ret
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Examples {
+  #[serde(default)]
+  positive: Vec<String>,
+  #[serde(default)]
+  negative: Vec<String>,
+  id: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExampleReport {
+  source: String,
+  expected: bool,
+  matched: bool,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InferredRule {
+  pattern: String,
+  config: Value,
+  yaml: String,
+  /// true if every example is matched or not matched as expected
+  passed: bool,
+  report: Vec<ExampleReport>,
+}
+
+/// anti-unify the positive examples into a pattern, replacing differing
+/// subtrees with meta variables, then exclude negative examples
+pub fn infer_rule(examples: Examples) -> Result<InferredRule, JsError> {
+  let lang = WasmLang::get_current();
+  if examples.positive.is_empty() {
+    return Err(JsError::new("at least one positive example is required"));
+  }
+  let positives = examples
+    .positive
+    .iter()
+    .map(|src| Ok(AstGrep::doc(WasmDoc::try_new(src.clone(), lang)?)))
+    .collect::<Result<Vec<_>, SgWasmError>>()?;
+  let roots: Vec<_> = positives.iter().map(|p| snippet_root(p.root())).collect();
+  if let Some(other) = roots.iter().find(|r| r.kind() != roots[0].kind()) {
+    let msg = format!("examples have different kinds: `{}` and `{}`", roots[0].kind(), other.kind());
+    return Err(JsError::new(&msg));
+  }
+  let mut generalizer = Generalizer::default();
+  generalizer.unify(&roots);
+  let pattern = replace_ranges(&roots[0], &generalizer.vars);
+  let compiled = Pattern::try_new(&pattern, lang)?;
+
+  let mut excluded: BTreeMap<String, Vec<String>> = BTreeMap::new();
+  let mut not_patterns = vec![];
+  for negative in &examples.negative {
+    let root = AstGrep::doc(WasmDoc::try_new(negative.clone(), lang)?);
+    let Some(nm) = root.root().find(&compiled) else {
+      continue;
+    };
+    let env = nm.get_env();
+    let distinct = generalizer.bindings.iter().find_map(|(name, texts)| {
+      let captured = env.get_match(name)?.text().to_string();
+      (!texts.contains(&captured)).then(|| (name.clone(), captured))
+    });
+    match distinct {
+      Some((name, captured)) => excluded.entry(name).or_default().push(captured),
+      None => not_patterns.push(json!({ "pattern": negative.trim() })),
+    }
+  }
+
+  let mut rule = json!({ "pattern": pattern });
+  if !not_patterns.is_empty() {
+    rule = json!({ "all": [rule, { "not": { "any": not_patterns } }] });
+  }
+  let mut config = json!({
+    "id": examples.id.as_deref().unwrap_or(DEFAULT_ID),
+    "language": lang.to_string(),
+    "rule": rule,
+  });
+  if !excluded.is_empty() {
+    let constraints: Map<_, _> = excluded
+      .into_iter()
+      .map(|(name, texts)| {
+        let texts: Vec<_> = texts.iter().map(|t| escape_regex(t)).collect();
+        let regex = format!("^({})$", texts.join("|"));
+        (name, json!({ "not": { "regex": regex } }))
+      })
+      .collect();
+    config["constraints"] = Value::Object(constraints);
+  }
+
+  let serialized: SerializableRuleConfig<WasmLang> = serde_json::from_value(config.clone())?;
+  let rule_config = RuleConfig::try_from(serialized, &Default::default())?;
+  let combined = CombinedScan::new(vec![&rule_config]);
+  let mut report = vec![];
+  for (sources, expected) in [(&examples.positive, true), (&examples.negative, false)] {
+    for source in sources {
+      let root = AstGrep::doc(WasmDoc::try_new(source.clone(), lang)?);
+      let matched = !combined.scan(&root, false).matches.is_empty();
+      report.push(ExampleReport {
+        source: source.clone(),
+        expected,
+        matched,
+      });
+    }
+  }
+  Ok(InferredRule {
+    pattern,
+    yaml: to_yaml(&config, &["id", "language", "rule", "constraints"]),
+    passed: report.iter().all(|r| r.expected == r.matched),
+    config,
+    report,
+  })
+}
+
+/// descend single child wrappers like `program` and `expression_statement`
+fn snippet_root(root: Node) -> Node {
+  let mut node = root;
+  loop {
+    let named: Vec<_> = node.children().filter(|n| n.is_named()).collect();
+    match named.as_slice() {
+      [child] if child.text().trim() == node.text().trim() => node = child.clone(),
+      _ => return node,
+    }
+  }
+}
+
+#[derive(Default)]
+struct Generalizer {
+  vars: Vec<(Range<usize>, String)>,
+  /// meta variable name without `$` and the text it binds in each positive example
+  bindings: Vec<(String, Vec<String>)>,
+}
+
+impl Generalizer {
+  fn unify(&mut self, nodes: &[Node]) {
+    let first = &nodes[0];
+    if nodes.iter().all(|n| n.text() == first.text()) {
+      return;
+    }
+    if can_descend(nodes) {
+      let children: Vec<Vec<_>> = nodes.iter().map(|n| n.children().collect()).collect();
+      for i in 0..children[0].len() {
+        let column: Vec<_> = children.iter().map(|c| c[i].clone()).collect();
+        self.unify(&column);
+      }
+      return;
+    }
+    let name = meta_var_name(self.bindings.len());
+    let texts = nodes.iter().map(|n| n.text().to_string()).collect();
+    self.vars.push((first.range(), format!("${}", name)));
+    self.bindings.push((name, texts));
+  }
+}
+
+/// nodes can be compared child by child if they have the same shape
+/// and only differ in named children, anonymous tokens cannot be meta variables
+fn can_descend(nodes: &[Node]) -> bool {
+  let first = &nodes[0];
+  let children: Vec<_> = first.children().collect();
+  if children.is_empty() {
+    return false;
+  }
+  nodes.iter().all(|n| {
+    n.kind() == first.kind()
+      && n.children().count() == children.len()
+      && n.children().zip(&children).all(|(a, b)| b.is_named() || a.text() == b.text())
+  })
+}
+
+fn meta_var_name(index: usize) -> String {
+  let letter = (b'A' + (index % 26) as u8) as char;
+  if index < 26 {
+    letter.to_string()
+  } else {
+    format!("{}{}", letter, index / 26)
+  }
+}
+
+fn escape_regex(text: &str) -> String {
+  let mut ret = String::new();
+  for c in text.chars() {
+    if "\\.+*?()|[]{}^$".contains(c) {
+      ret.push('\\');
+    }
+    ret.push(c);
+  }
+  ret
+}
+
+/// serialize a json config to yaml, writing top level keys in the given order
+pub fn to_yaml(config: &Value, keys: &[&str]) -> String {
+  let mut map = serde_yaml::Mapping::new();
+  for key in keys {
+    if let Some(value) = config.get(key) {
+      let value = serde_yaml::to_value(value).unwrap_or_default();
+      map.insert((*key).into(), value);
+    }
+  }
+  serde_yaml::to_string(&map).unwrap_or_default()
+}
+
+#[cfg(all(test, feature = "native"))]
+mod native_test {
+  use super::*;
+  use crate::test::use_langs;
+
+  fn selection(value: Value) -> Selection {
+    serde_json::from_value(value).expect("selection should be valid")
+  }
+
+  #[test]
+  fn test_pattern_from_selection() {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let src = "if (x) {\n  console.log('😀', foo(a, b))\n}";
+    let selected = selection(json!({
+      "start": { "row": 1, "column": 2 },
+      "end": { "row": 1, "column": 30 },
+      "metaVars": [
+        { "name": "$MSG", "start": { "row": 1, "column": 14 }, "end": { "row": 1, "column": 18 } },
+        { "name": "$$$ARGS", "start": { "row": 1, "column": 24 }, "end": { "row": 1, "column": 28 } },
+      ],
+    }));
+    let generated = pattern_from_selection(src.into(), selected).unwrap_or_else(|_| panic!("should generate"));
+    assert_eq!(generated.kind, "call_expression");
+    assert_eq!(generated.pattern, "console.log($MSG, foo($$$ARGS))");
+    assert_eq!(generated.context, None);
+    let expected = "id: generated-rule\nlanguage: javascript\nrule:\n  pattern: console.log($MSG, foo($$$ARGS))\n";
+    assert_eq!(generated.yaml, expected);
+  }
+
+  #[test]
+  fn test_pattern_from_selection_context() {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let src = "class A {\n  foo = 1\n}";
+    let selected = selection(json!({ "start": { "row": 1, "column": 2 }, "end": { "row": 1, "column": 9 }, "id": "field" }));
+    let generated = pattern_from_selection(src.into(), selected).unwrap_or_else(|_| panic!("should generate"));
+    assert_eq!(generated.kind, "field_definition");
+    assert_eq!(generated.context.as_deref(), Some("class A {\n  foo = 1\n}"));
+    assert_eq!(generated.selector.as_deref(), Some("field_definition"));
+    assert!(generated.yaml.starts_with("id: field\n"));
+  }
+
+  fn infer(positive: &[&str], negative: &[&str]) -> InferredRule {
+    let examples = Examples {
+      positive: positive.iter().map(|s| s.to_string()).collect(),
+      negative: negative.iter().map(|s| s.to_string()).collect(),
+      id: None,
+    };
+    infer_rule(examples).unwrap_or_else(|_| panic!("should infer"))
+  }
+
+  #[test]
+  fn test_infer_pattern() {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let inferred = infer(&["console.log(a, 1)", "console.log(b, 1)"], &["console.warn(a, 1)"]);
+    assert_eq!(inferred.pattern, "console.log($A, 1)");
+    assert_eq!(inferred.config["rule"], json!({ "pattern": "console.log($A, 1)" }));
+    assert!(inferred.passed);
+    let report: Vec<_> = inferred.report.iter().map(|r| (r.expected, r.matched)).collect();
+    assert_eq!(report, [(true, true), (true, true), (false, false)]);
+  }
+
+  #[test]
+  fn test_infer_constraints() {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let inferred = infer(&["foo(a)", "foo(b)"], &["foo(c.d)", "bar(a)"]);
+    assert_eq!(inferred.pattern, "foo($A)");
+    assert_eq!(inferred.config["constraints"], json!({ "A": { "not": { "regex": "^(c\\.d)$" } } }));
+    assert!(inferred.passed);
+    let expected = "id: generated-rule\nlanguage: javascript\nrule:\n  pattern: foo($A)\nconstraints:\n  A:\n    not:\n      regex: ^(c\\.d)$\n";
+    assert_eq!(inferred.yaml, expected);
+  }
+
+  #[test]
+  fn test_infer_not() {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    // the negative binds `$A` to a text seen in a positive, so only `not` can exclude it
+    let inferred = infer(&["foo(a, 1)", "foo(b, 1)"], &["foo(a, 1)"]);
+    let rule = json!({ "all": [{ "pattern": "foo($A, 1)" }, { "not": { "any": [{ "pattern": "foo(a, 1)" }] } }] });
+    assert_eq!(inferred.config["rule"], rule);
+    assert!(!inferred.passed);
+    let report: Vec<_> = inferred.report.iter().map(|r| (r.expected, r.matched)).collect();
+    assert_eq!(report, [(true, false), (true, true), (false, false)]);
+  }
+}
@@ -0,0 +1,334 @@
+use crate::ts;
+use crate::wasm_lang::{SgWasmError, WasmLang};
+use serde::{Deserialize, Serialize};
+use once_cell::sync::Lazy;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use wasm_bindgen::UnwrapThrowExt;
+#[cfg(not(feature = "native"))]
+use wasm_bindgen::prelude::*;
+
+// web-tree-sitter-sg does not bind `Language.supertypes`
+#[cfg(not(feature = "native"))]
+#[wasm_bindgen]
+extern "C" {
+  type SupertypesLanguage;
+  #[wasm_bindgen(method, getter)]
+  fn supertypes(this: &SupertypesLanguage) -> Box<[JsValue]>;
+}
+
+#[cfg(not(feature = "native"))]
+fn supertypes(ts_lang: &ts::Language) -> HashSet<u16> {
+  let ids = ts_lang.unchecked_ref::<SupertypesLanguage>().supertypes();
+  ids.iter().filter_map(JsValue::as_f64).map(|id| id as u16).collect()
+}
+
+#[cfg(feature = "native")]
+fn supertypes(ts_lang: &ts::Language) -> HashSet<u16> {
+  ts_lang.supertypes().into_iter().collect()
+}
+
+static GRAMMARS: Lazy<Mutex<HashMap<WasmLang, Arc<Grammar>>>> = Lazy::new(Default::default);
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeKindInfo {
+  id: u16,
+  kind: String,
+  is_named: bool,
+  is_visible: bool,
+  is_supertype: bool,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldInfo {
+  id: u16,
+  name: String,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Grammar {
+  kinds: Vec<NodeKindInfo>,
+  fields: Vec<FieldInfo>,
+}
+
+/// report unknown names as warnings instead of errors in lenient mode
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CheckOptions {
+  #[serde(default)]
+  pub lenient: bool,
+}
+
+#[derive(Serialize)]
+pub struct RuleCheck {
+  pub warnings: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnknownName {
+  pub name: String,
+  pub is_field: bool,
+  pub suggestions: Vec<String>,
+}
+
+impl fmt::Display for UnknownName {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let category = if self.is_field { "field" } else { "kind" };
+    write!(f, "Unknown {} `{}`.", category, self.name)?;
+    if !self.suggestions.is_empty() {
+      let names: Vec<_> = self.suggestions.iter().map(|s| format!("`{}`", s)).collect();
+      write!(f, " Did you mean {}?", names.join(", "))?;
+    }
+    Ok(())
+  }
+}
+
+#[derive(Debug)]
+pub struct UnknownNames(Vec<String>);
+
+impl std::error::Error for UnknownNames {}
+
+impl fmt::Display for UnknownNames {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.0.join("\n"))
+  }
+}
+
+impl Grammar {
+  /// the grammar of a loaded language, built once per parser
+  pub fn of(lang: WasmLang) -> Result<Arc<Self>, SgWasmError> {
+    let mut grammars = GRAMMARS.lock().expect_throw("grammar cache error");
+    if let Some(grammar) = grammars.get(&lang) {
+      return Ok(grammar.clone());
+    }
+    let grammar = Arc::new(Self::new(lang)?);
+    grammars.insert(lang, grammar.clone());
+    Ok(grammar)
+  }
+
+  /// drop the cached grammar when a new parser is loaded for the language
+  pub fn forget(lang: WasmLang) {
+    GRAMMARS.lock().expect_throw("grammar cache error").remove(&lang);
+  }
+
+  fn new(lang: WasmLang) -> Result<Self, SgWasmError> {
+    let ts_lang = lang.get_ts_language()?;
+    let supertypes = supertypes(&ts_lang);
+    let kinds = (0..ts_lang.node_kind_count())
+      .filter_map(|id| {
+        let kind = ts_lang.node_kind_for_id(id)?;
+        Some(NodeKindInfo {
+          id,
+          kind,
+          is_named: ts_lang.node_kind_is_named(id),
+          is_visible: ts_lang.node_kind_is_visible(id),
+          is_supertype: supertypes.contains(&id),
+        })
+      })
+      .collect();
+    // field id 0 is reserved for "no field"
+    let fields = (1..=ts_lang.field_count())
+      .filter_map(|id| {
+        let name = ts_lang.field_name_for_id(id)?;
+        Some(FieldInfo { id, name })
+      })
+      .collect();
+    Ok(Self { kinds, fields })
+  }
+
+  pub fn has_kind(&self, kind: &str) -> bool {
+    self.kinds.iter().any(|k| k.is_named && k.kind == kind)
+  }
+
+  pub fn has_field(&self, field: &str) -> bool {
+    self.fields.iter().any(|f| f.name == field)
+  }
+
+  pub fn suggest_kinds(&self, kind: &str) -> Vec<String> {
+    let candidates = self.kinds.iter().filter(|k| k.is_named).map(|k| k.kind.as_str());
+    suggest(kind, candidates)
+  }
+
+  pub fn suggest_fields(&self, field: &str) -> Vec<String> {
+    suggest(field, self.fields.iter().map(|f| f.name.as_str()))
+  }
+
+  /// collect `kind`, `selector` and `field` names in a serialized rule
+  /// that do not exist in the grammar
+  pub fn find_unknown_names(&self, rule: &Value) -> Vec<UnknownName> {
+    let mut ret = vec![];
+    self.visit_names(rule, &mut ret);
+    ret
+  }
+
+  /// check names in a serialized rule, returning them as warnings in lenient mode
+  pub fn validate(&self, rule: &Value, options: &CheckOptions) -> Result<Vec<String>, UnknownNames> {
+    let unknown = self.find_unknown_names(rule);
+    let id = rule.get("id").and_then(Value::as_str).unwrap_or_default();
+    let messages: Vec<_> = unknown.iter().map(|u| format!("Rule `{}`: {}", id, u)).collect();
+    if messages.is_empty() || options.lenient {
+      Ok(messages)
+    } else {
+      Err(UnknownNames(messages))
+    }
+  }
+
+  fn visit_names(&self, value: &Value, ret: &mut Vec<UnknownName>) {
+    match value {
+      Value::Array(items) => {
+        for item in items {
+          self.visit_names(item, ret);
+        }
+      }
+      Value::Object(map) => {
+        for (key, val) in map {
+          match (key.as_str(), val) {
+            // metadata is user defined and not a rule
+            ("metadata", _) => continue,
+            ("kind" | "selector", Value::String(kind)) if !self.has_kind(kind) => {
+              ret.push(UnknownName {
+                name: kind.clone(),
+                is_field: false,
+                suggestions: self.suggest_kinds(kind),
+              });
+            }
+            ("field", Value::String(field)) if !self.has_field(field) => {
+              ret.push(UnknownName {
+                name: field.clone(),
+                is_field: true,
+                suggestions: self.suggest_fields(field),
+              });
+            }
+            _ => self.visit_names(val, ret),
+          }
+        }
+      }
+      _ => {}
+    }
+  }
+}
+
+const MAX_SUGGESTIONS: usize = 3;
+
+/// rank candidates by edit distance, also accepting names containing the input
+fn suggest<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Vec<String> {
+  let threshold = (name.chars().count() / 3).max(2);
+  let mut scored: Vec<_> = candidates
+    .filter_map(|c| {
+      let distance = edit_distance(name, c);
+      if distance <= threshold || c.contains(name) {
+        Some((distance, c))
+      } else {
+        None
+      }
+    })
+    .collect();
+  scored.sort();
+  scored.dedup();
+  scored.into_iter().take(MAX_SUGGESTIONS).map(|(_, c)| c.to_string()).collect()
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+  let b: Vec<_> = b.chars().collect();
+  let mut prev: Vec<_> = (0..=b.len()).collect();
+  for (i, ca) in a.chars().enumerate() {
+    let mut curr = vec![i + 1; b.len() + 1];
+    for (j, &cb) in b.iter().enumerate() {
+      let cost = if ca == cb { 0 } else { 1 };
+      curr[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(curr[j] + 1);
+    }
+    prev = curr;
+  }
+  prev[b.len()]
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_edit_distance() {
+    assert_eq!(edit_distance("kind", "kind"), 0);
+    assert_eq!(edit_distance("fucntion", "function"), 2);
+    assert_eq!(edit_distance("", "abc"), 3);
+    assert_eq!(edit_distance("identifer", "identifier"), 1);
+  }
+
+  #[test]
+  fn test_suggest() {
+    let candidates = ["function_declaration", "function_expression", "identifier", "if_statement"];
+    let found = suggest("fucntion_declaration", candidates.iter().copied());
+    assert_eq!(found, ["function_declaration"]);
+    let found = suggest("function", candidates.iter().copied());
+    assert_eq!(found, ["function_expression", "function_declaration"]);
+    assert!(suggest("class", candidates.iter().copied()).is_empty());
+  }
+}
+
+#[cfg(all(test, feature = "native"))]
+mod native_test {
+  use super::*;
+  use crate::test::use_langs;
+  use serde_json::json;
+
+  fn kind<'a>(grammar: &'a Grammar, name: &str) -> &'a NodeKindInfo {
+    grammar.kinds.iter().find(|k| k.kind == name).expect("kind should exist")
+  }
+
+  #[test]
+  fn test_grammar() {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let grammar = Grammar::of(WasmLang::JavaScript).expect("grammar should load");
+    let call = kind(&grammar, "call_expression");
+    assert!(call.is_named && call.is_visible && !call.is_supertype);
+    let paren = kind(&grammar, "(");
+    assert!(!paren.is_named && paren.is_visible);
+    assert!(kind(&grammar, "expression").is_supertype);
+    assert!(kind(&grammar, "statement").is_supertype);
+    assert!(grammar.has_kind("call_expression"));
+    assert!(!grammar.has_kind("("));
+    assert!(grammar.has_field("arguments"));
+    assert!(!grammar.has_field("argument_list"));
+    let field = grammar.fields.iter().find(|f| f.name == "function").expect("field should exist");
+    let ts_lang = WasmLang::JavaScript.get_ts_language().expect("language should be loaded");
+    assert_eq!(ts_lang.field_id_for_name("function"), Some(field.id));
+    assert_eq!(ts_lang.id_for_node_kind("call_expression", true), call.id);
+  }
+
+  #[test]
+  fn test_unknown_names() {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let grammar = Grammar::of(WasmLang::JavaScript).expect("grammar should load");
+    let rule = json!({
+      "id": "typo",
+      "rule": {
+        "kind": "fucntion_declaration",
+        "has": { "kind": "identifier", "field": "nmae" },
+        "pattern": { "context": "a = 1", "selector": "assignment_expresion" },
+      },
+      "metadata": { "kind": "not a rule" },
+    });
+    let unknown = grammar.find_unknown_names(&rule);
+    let names: Vec<_> = unknown.iter().map(|u| (u.name.as_str(), u.is_field)).collect();
+    assert_eq!(
+      names,
+      [("fucntion_declaration", false), ("nmae", true), ("assignment_expresion", false)]
+    );
+    assert_eq!(unknown[0].suggestions[0], "function_declaration");
+    assert_eq!(unknown[1].suggestions, ["name"]);
+    assert_eq!(unknown[2].to_string(), "Unknown kind `assignment_expresion`. Did you mean `assignment_expression`?");
+    let err = grammar.validate(&rule, &CheckOptions::default()).expect_err("should fail");
+    assert!(err.to_string().starts_with("Rule `typo`: Unknown kind `fucntion_declaration`."));
+    let lenient = CheckOptions { lenient: true };
+    let warnings = grammar.validate(&rule, &lenient).expect("should only warn");
+    assert_eq!(warnings.len(), 3);
+    let valid = json!({ "id": "ok", "rule": { "kind": "identifier" } });
+    assert!(grammar.validate(&valid, &lenient).expect("should pass").is_empty());
+  }
+}
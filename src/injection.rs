@@ -0,0 +1,179 @@
+use crate::rewrite::RewriteTrace;
+use crate::utils::{WasmMatch, WasmNode};
+use crate::wasm_lang::{SgWasmError, WasmDoc, WasmLang};
+use ast_grep_core::{AstGrep, Node as SgNode};
+
+type Node<'a> = SgNode<'a, WasmDoc>;
+
+/// A region of the host document written in another language,
+/// e.g. `<script>` in HTML or a styled-components template in JavaScript.
+pub struct Injection {
+  pub lang: WasmLang,
+  pub root: AstGrep<WasmDoc>,
+  /// offset of the region in the host document, in UTF-16 code units like node ranges
+  pub offset: usize,
+  pub row: usize,
+  pub column: usize,
+}
+
+impl Injection {
+  fn try_new(lang: WasmLang, node: &Node, trim: usize) -> Result<Self, SgWasmError> {
+    let text: Vec<_> = node.text().chars().collect();
+    let end = text.len().saturating_sub(trim).max(trim);
+    let src = text[trim..end].iter().collect();
+    let start = node.get_inner_node().0.start_position();
+    Ok(Self {
+      lang,
+      root: AstGrep::doc(WasmDoc::try_new(src, lang)?),
+      offset: node.range().start + trim,
+      row: start.row() as usize,
+      column: start.column() as usize + trim,
+    })
+  }
+
+  /// map a position in the injected document back to the host document
+  pub fn map_point(&self, row: usize, column: usize) -> (usize, usize) {
+    if row == 0 {
+      (self.row, self.column + column)
+    } else {
+      (self.row + row, column)
+    }
+  }
+
+  pub fn map_offset(&self, offset: usize) -> usize {
+    self.offset + offset
+  }
+
+  pub fn map_node(&self, node: &mut WasmNode) {
+    let (start_row, start_col, end_row, end_col) = node.range;
+    let (start_row, start_col) = self.map_point(start_row, start_col);
+    let (end_row, end_col) = self.map_point(end_row, end_col);
+    node.range = (start_row, start_col, end_row, end_col);
+  }
+
+  pub fn map_match(&self, nm: &mut WasmMatch) {
+    self.map_node(&mut nm.node);
+    for node in nm.env.values_mut() {
+      self.map_node(node);
+    }
+    self.map_rewrites(&mut nm.rewrites);
+  }
+
+  fn map_rewrites(&self, rewrites: &mut [RewriteTrace]) {
+    for rewrite in rewrites {
+      self.map_node(&mut rewrite.node);
+      self.map_rewrites(&mut rewrite.nested);
+    }
+  }
+}
+
+/// Find all regions in the host document whose language parser is loaded.
+pub fn extract_injections(root: &AstGrep<WasmDoc>) -> Result<Vec<Injection>, SgWasmError> {
+  let host = *root.root().lang();
+  let mut ret = vec![];
+  for node in root.root().dfs() {
+    let Some((lang, content, trim)) = detect_injection(host, &node) else {
+      continue;
+    };
+    if !lang.is_loaded() {
+      continue;
+    }
+    ret.push(Injection::try_new(lang, &content, trim)?);
+  }
+  Ok(ret)
+}
+
+/// returns the injected language, the node holding the embedded source
+/// and how many delimiter chars to trim on both sides of the node text
+fn detect_injection<'r>(host: WasmLang, node: &Node<'r>) -> Option<(WasmLang, Node<'r>, usize)> {
+  use WasmLang as W;
+  match (host, &*node.kind()) {
+    (W::Html, "script_element") => {
+      let lang = script_lang(node)?;
+      let content = node.children().find(|n| n.kind() == "raw_text")?;
+      Some((lang, content, 0))
+    }
+    (W::Html, "style_element") => {
+      let content = node.children().find(|n| n.kind() == "raw_text")?;
+      Some((W::Css, content, 0))
+    }
+    (W::JavaScript | W::TypeScript | W::Tsx, "call_expression") => {
+      let content = node.field("arguments")?;
+      if content.kind() != "template_string" {
+        return None;
+      }
+      let tag = node.field("function")?;
+      let lang = template_lang(&tag.text())?;
+      Some((lang, content, 1))
+    }
+    _ => None,
+  }
+}
+
+fn script_lang(script: &Node) -> Option<WasmLang> {
+  let start_tag = script.children().find(|n| n.kind() == "start_tag")?;
+  for attr in start_tag.children().filter(|n| n.kind() == "attribute") {
+    let Some(name) = attr.children().find(|n| n.kind() == "attribute_name") else {
+      continue;
+    };
+    let value = attr
+      .dfs()
+      .find(|n| n.kind() == "attribute_value")
+      .map(|n| n.text().to_string())
+      .unwrap_or_default();
+    match (&*name.text(), value.as_str()) {
+      ("lang", "ts" | "typescript") => return Some(WasmLang::TypeScript),
+      ("lang", "tsx") => return Some(WasmLang::Tsx),
+      ("type", "application/json" | "importmap") => return Some(WasmLang::Json),
+      ("type", "module" | "text/javascript" | "application/javascript") => {}
+      // other script types, e.g. text/template, are not code
+      ("type", _) => return None,
+      _ => {}
+    }
+  }
+  Some(WasmLang::JavaScript)
+}
+
+fn template_lang(tag: &str) -> Option<WasmLang> {
+  match tag {
+    "css" | "keyframes" | "createGlobalStyle" | "injectGlobal" => Some(WasmLang::Css),
+    "html" | "svg" => Some(WasmLang::Html),
+    _ if tag.starts_with("styled.") || tag.starts_with("styled(") => Some(WasmLang::Css),
+    _ => None,
+  }
+}
+
+#[cfg(all(test, feature = "native"))]
+mod test {
+  use super::*;
+  use crate::test::use_langs;
+
+  fn injections(src: &str, host: WasmLang) -> Vec<Injection> {
+    let root = AstGrep::doc(WasmDoc::try_new(src.into(), host).expect("should parse"));
+    extract_injections(&root).expect("should extract")
+  }
+
+  #[test]
+  fn test_extract_from_html() {
+    let _lock = use_langs(&[WasmLang::JavaScript, WasmLang::Css, WasmLang::Json, WasmLang::Html]);
+    let src = "<div></div>\n<script>let a = 1</script>\n<style>p { color: red }</style>\n\
+      <script type=\"text/template\"><p></p></script>\n<script type=\"application/json\">{}</script>";
+    let found = injections(src, WasmLang::Html);
+    let langs: Vec<_> = found.iter().map(|i| i.lang).collect();
+    assert_eq!(langs, [WasmLang::JavaScript, WasmLang::Css, WasmLang::Json]);
+    assert_eq!((found[0].row, found[0].column, found[0].offset), (1, 8, 20));
+    assert_eq!(found[0].root.root().text(), "let a = 1");
+    assert_eq!(found[1].root.root().text(), "p { color: red }");
+  }
+
+  #[test]
+  fn test_extract_from_template() {
+    let _lock = use_langs(&[WasmLang::Css, WasmLang::JavaScript]);
+    let src = "const a = 1\nconst B = styled.div`color: red;`\nfoo`color: blue;`";
+    let found = injections(src, WasmLang::JavaScript);
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].lang, WasmLang::Css);
+    assert_eq!((found[0].row, found[0].column), (1, 21));
+    assert_eq!(found[0].root.root().text(), "color: red;");
+  }
+}
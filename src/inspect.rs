@@ -0,0 +1,154 @@
+use crate::dump_tree::Pos;
+use crate::ts::Point;
+use crate::utils::WasmMatch;
+use crate::wasm_lang::{SgWasmError, WasmDoc, WasmLang};
+use ast_grep_config::RuleConfig;
+use ast_grep_core::matcher::MatcherExt;
+use ast_grep_core::{AstGrep, Node};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CursorQuery {
+  position: Pos,
+  /// skip anonymous nodes like punctuation when picking the deepest node
+  #[serde(default)]
+  named_only: bool,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PathNode {
+  id: usize,
+  kind: String,
+  /// field of the node in its parent
+  field: Option<String>,
+  start: Pos,
+  end: Pos,
+  is_named: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AncestorMatch {
+  rule_id: String,
+  /// index of the matched node in `path`
+  depth: usize,
+  #[serde(flatten)]
+  matched: WasmMatch,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CursorInfo {
+  /// from the root to the deepest node at the position
+  path: Vec<PathNode>,
+  /// rules matching any node of the path, innermost first
+  matches: Vec<AncestorMatch>,
+}
+
+pub fn inspect_position(
+  src: String,
+  lang: WasmLang,
+  rules: &[RuleConfig<WasmLang>],
+  query: &CursorQuery,
+) -> Result<CursorInfo, SgWasmError> {
+  let doc = WasmDoc::try_new(src, lang)?;
+  let root = AstGrep::doc(doc);
+  let mut info = CursorInfo {
+    path: vec![],
+    matches: vec![],
+  };
+  let point = Point::from(&query.position);
+  let root_node = root.root();
+  let inner = &root_node.get_inner_node().0;
+  let deepest = if query.named_only {
+    inner.named_descendant_for_position_range(&point, &point)
+  } else {
+    inner.descendant_for_position_range(&point, &point)
+  };
+  let Some(deepest) = deepest else {
+    return Ok(info);
+  };
+  let id = deepest.id() as usize;
+  let Some(node) = root_node.dfs().find(|n| n.node_id() == id) else {
+    return Ok(info);
+  };
+  let mut nodes: Vec<_> = node.ancestors().collect();
+  nodes.reverse();
+  nodes.push(node);
+  for rule in rules.iter().filter(|r| r.language == lang) {
+    for (depth, node) in nodes.iter().enumerate() {
+      if let Some(nm) = rule.matcher.match_node(node.clone()) {
+        info.matches.push(AncestorMatch {
+          rule_id: rule.id.clone(),
+          depth,
+          matched: WasmMatch::from_match(nm, rule),
+        });
+      }
+    }
+  }
+  info.matches.sort_by_key(|m| Reverse(m.depth));
+  info.path = nodes.iter().map(path_node).collect();
+  Ok(info)
+}
+
+fn path_node(node: &Node<WasmDoc>) -> PathNode {
+  let inner = &node.get_inner_node().0;
+  PathNode {
+    id: node.node_id(),
+    kind: node.kind().to_string(),
+    field: field_name(node),
+    start: inner.start_position().into(),
+    end: inner.end_position().into(),
+    is_named: node.is_named(),
+  }
+}
+
+fn field_name(node: &Node<WasmDoc>) -> Option<String> {
+  let parent = node.parent()?;
+  let cursor = parent.get_inner_node().0.walk();
+  if !cursor.goto_first_child() {
+    return None;
+  }
+  while cursor.current_node().id() as usize != node.node_id() {
+    if !cursor.goto_next_sibling() {
+      return None;
+    }
+  }
+  cursor.current_field_name().map(|f| format!("{}", f))
+}
+
+#[cfg(all(test, feature = "native"))]
+mod test {
+  use super::*;
+  use crate::rule_from_value;
+  use crate::test::use_langs;
+  use serde_json::json;
+
+  #[test]
+  fn test_inspect_position() {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let rule = rule_from_value(json!({
+      "id": "no-log",
+      "language": "javascript",
+      "rule": { "pattern": "console.log($A)" },
+    }));
+    let rules = vec![rule.expect("rule should be valid")];
+    let query: CursorQuery = serde_json::from_value(json!({ "position": { "row": 0, "column": 16 } }))
+      .expect("query should be valid");
+    let src = "foo(console.log(a + b))";
+    let info = inspect_position(src.into(), WasmLang::JavaScript, &rules, &query).expect("should parse");
+    let kinds: Vec<_> = info.path.iter().map(|n| n.kind.as_str()).collect();
+    assert_eq!(kinds[0], "program");
+    assert_eq!(kinds[kinds.len() - 2..], ["binary_expression", "identifier"]);
+    assert_eq!(info.path[kinds.len() - 1].field.as_deref(), Some("left"));
+    assert_eq!(info.matches.len(), 1);
+    let found = &info.matches[0];
+    assert_eq!(kinds[found.depth], "call_expression");
+    let start = serde_json::to_value(&info.path[found.depth].start).expect("should serialize");
+    assert_eq!(start, json!({ "row": 0, "column": 4 }));
+    assert_eq!(found.matched.env["A"].text, "a + b");
+  }
+}
@@ -1,21 +1,62 @@
+#[cfg(all(test, feature = "native"))]
+mod conformance;
+mod diagnostics;
+mod diff;
 mod dump_tree;
+mod generate;
+mod grammar;
+mod injection;
+mod inspect;
+mod lsp;
+mod near_miss;
+#[cfg(feature = "native")]
+mod native;
+mod render;
+mod report;
+mod rewrite;
+mod scan;
+mod share;
+mod tree_diff;
 mod utils;
 mod wasm_lang;
 
-use wasm_lang::{WasmDoc, WasmLang};
-use dump_tree::{dump_one_node, DumpNode, dump_pattern as dump_pattern_impl};
-use utils::WasmMatch;
+#[cfg(feature = "native")]
+use native as ts;
+#[cfg(not(feature = "native"))]
+use web_tree_sitter_sg as ts;
+
+use wasm_lang::{WasmDoc, WasmLang, Wrapper};
+use injection::{extract_injections, Injection};
+use inspect::{inspect_position as inspect_position_impl, CursorQuery};
+use generate::{infer_rule as infer_rule_impl, pattern_from_selection as pattern_from_selection_impl};
+use grammar::{CheckOptions, Grammar, RuleCheck, UnknownName};
+use render::{render_graph, render_tree, GraphOptions, RenderOptions};
+use report::{Report, ReportOptions};
+use dump_tree::{dump_ast, suggest_selectors as suggest_selectors_impl, DumpOptions, dump_pattern as dump_pattern_impl};
+use diagnostics::syntax_diagnostics;
+use diff::{DiffOptions, FixDiff};
+use lsp::Server;
+use near_miss::{root_near_misses, DebugMatches};
+use scan::{
+  now, Budget, BudgetedMatches, MatchCallback, Page, Paginator, ProfiledMatches, RootScan,
+  ScanBudget, ScanProfile, ScanStatus, StreamSummary,
+};
+use tree_diff::structural_diff as structural_diff_impl;
+use share::{decode_state, encode_state, validate_state, ShareScan, ShareState};
+use rewrite::{Replaced, RewriteTracer};
+use utils::{FindOptions, WasmMatch};
 
 use ast_grep_config::{RuleConfig, SerializableRuleConfig, CombinedScan};
-use ast_grep_core::{AstGrep, Node as SgNode};
+use ast_grep_core::AstGrep;
+use ast_grep_core::source::Edit;
 use serde_wasm_bindgen::from_value as from_js_val;
 use std::collections::HashMap;
 use std::error::Error;
-use web_tree_sitter_sg::TreeSitter;
+use std::str::FromStr;
+use ts::TreeSitter;
 use wasm_bindgen::prelude::*;
 
-type Node<'a> = SgNode<'a, WasmDoc>;
-
+#[cfg(target_arch = "wasm32")]
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
@@ -29,76 +70,352 @@ pub async fn setup_parser(lang_name: String, parser_path: String) -> Result<(),
   WasmLang::set_current(&lang_name, &parser_path).await
 }
 
+#[wasm_bindgen(js_name = registerParser)]
+pub async fn register_parser(lang_name: String, parser_path: String) -> Result<(), JsError> {
+  WasmLang::register(&lang_name, &parser_path).await
+}
+
 #[wasm_bindgen(js_name = findNodes)]
-pub fn find_nodes(src: String, configs: Vec<JsValue>) -> Result<JsValue, JsError> {
-  let lang = WasmLang::get_current();
-  let mut rules = vec![];
-  for config in configs {
-    let finder = try_get_rule_config(config)?;
-    rules.push(finder);
+pub fn find_nodes(src: String, configs: Vec<JsValue>, options: JsValue) -> Result<JsValue, JsError> {
+  let options: Option<FindOptions> = from_js_val(options)?;
+  let rules = try_get_rule_configs(configs)?;
+  let ret = find_matches(src, &rules, &options.unwrap_or_default()).map_err(|e| JsError::new(&e))?;
+  let ret = serde_wasm_bindgen::to_value(&ret)?;
+  Ok(ret)
+}
+
+fn find_matches(
+  src: String,
+  rules: &[RuleConfig<WasmLang>],
+  options: &FindOptions,
+) -> Result<HashMap<String, Vec<WasmMatch>>, String> {
+  let doc = WasmDoc::cached(src, WasmLang::get_current()).map_err(error_message)?;
+  root_matches(&AstGrep::doc(doc), rules, options)
+}
+
+/// matches of the host and injected documents
+fn root_matches(
+  root: &AstGrep<WasmDoc>,
+  rules: &[RuleConfig<WasmLang>],
+  options: &FindOptions,
+) -> Result<HashMap<String, Vec<WasmMatch>>, String> {
+  let mut ret = scan_matches(root, rules, None, options);
+  for injection in extract_injections(root).map_err(error_message)? {
+    let found = scan_matches(&injection.root, rules, Some(&injection), options);
+    for (id, matches) in found {
+      ret.entry(id).or_default().extend(matches);
+    }
   }
-  let combined = CombinedScan::new(rules.iter().collect());
-  let doc = WasmDoc::try_new(src.clone(), lang)?;
-  let root = AstGrep::doc(doc);
-  let ret: HashMap<_, _> = combined.scan(&root, false).matches.into_iter().map(|(rule, matches)| {
-    let matches: Vec<_> = matches.into_iter().map(|m| {
-      WasmMatch::from_match(m, rule)
+  Ok(ret)
+}
+
+fn scan_matches(
+  root: &AstGrep<WasmDoc>,
+  rules: &[RuleConfig<WasmLang>],
+  injection: Option<&Injection>,
+  options: &FindOptions,
+) -> HashMap<String, Vec<WasmMatch>> {
+  let lang = *root.root().lang();
+  let rules: Vec<_> = rules.iter().filter(|r| r.language == lang).collect();
+  if rules.is_empty() {
+    return HashMap::new();
+  }
+  // tracing is best effort, a rule whose rewriters cannot be replayed still matches
+  let tracers: HashMap<_, _> = rules
+    .iter()
+    .filter(|_| options.trace_rewrites)
+    .filter_map(|r| Some((r.id.as_str(), RewriteTracer::new(r).ok()??)))
+    .collect();
+  let combined = CombinedScan::new(rules);
+  combined.scan(root, false).matches.into_iter().map(|(rule, matches)| {
+    let tracer = tracers.get(rule.id.as_str());
+    let matches: Vec<_> = matches.into_iter().map(|nm| {
+      let rewrites = tracer.map(|t| t.trace(&nm)).unwrap_or_default();
+      let mut m = WasmMatch::from_match(nm, rule);
+      m.rewrites = rewrites;
+      if let Some(injection) = injection {
+        injection.map_match(&mut m);
+      }
+      m
     }).collect();
     (rule.id.clone(), matches)
-  }).collect();
+  }).collect()
+}
+
+#[wasm_bindgen(js_name = findNodesDebug)]
+pub fn find_nodes_debug(src: String, configs: Vec<JsValue>) -> Result<JsValue, JsError> {
+  let rules = try_get_rule_configs(configs)?;
+  let doc = WasmDoc::cached(src, WasmLang::get_current())?;
+  let root = AstGrep::doc(doc);
+  let ret = DebugMatches {
+    matches: root_matches(&root, &rules, &Default::default()).map_err(|e| JsError::new(&e))?,
+    near_misses: root_near_misses(&root, &rules).map_err(|e| JsError::new(&e))?,
+  };
   let ret = serde_wasm_bindgen::to_value(&ret)?;
   Ok(ret)
 }
 
-#[wasm_bindgen(js_name = fixErrors)]
-pub fn fix_errors(src: String, configs: Vec<JsValue>) -> Result<String, JsError> {
+#[wasm_bindgen(js_name = findNodesWithBudget)]
+pub fn find_nodes_with_budget(
+  src: String,
+  configs: Vec<JsValue>,
+  budget: JsValue,
+) -> Result<JsValue, JsError> {
   let lang = WasmLang::get_current();
-  let mut rules = vec![];
-  for config in configs {
-    let finder = try_get_rule_config(config)?;
-    rules.push(finder);
+  let rules = try_get_rule_configs(configs)?;
+  let limit: Option<ScanBudget> = from_js_val(budget)?;
+  let mut budget = Budget::new(limit.unwrap_or_default());
+  let doc = WasmDoc::cached(src, lang)?;
+  let root = AstGrep::doc(doc);
+  let injections = extract_injections(&root)?;
+  let matches = RootScan::new(&root, &injections, &rules).scan(&mut budget);
+  let ret = BudgetedMatches {
+    matches,
+    status: budget.status,
+    visited: budget.visited,
+  };
+  let ret = serde_wasm_bindgen::to_value(&ret)?;
+  Ok(ret)
+}
+
+#[wasm_bindgen(js_name = profileFindNodes)]
+pub fn profile_find_nodes(src: String, configs: Vec<JsValue>) -> Result<JsValue, JsError> {
+  let lang = WasmLang::get_current();
+  let rules = try_get_rule_configs(configs)?;
+  let mut profile = ScanProfile::default();
+  let start = now();
+  let doc = WasmDoc::try_new(src, lang)?;
+  let root = AstGrep::doc(doc);
+  let injections = extract_injections(&root)?;
+  profile.parse_ms = now() - start;
+  let start = now();
+  let mut matches = HashMap::new();
+  profile.scan(&root, None, &rules, &mut matches);
+  for injection in &injections {
+    profile.scan(&injection.root, Some(injection), &rules, &mut matches);
   }
-  let combined = CombinedScan::new(rules.iter().collect());
-  let doc = WasmDoc::try_new(src.clone(), lang)?;
+  profile.scan_ms = now() - start;
+  let ret = ProfiledMatches { matches, profile };
+  let ret = serde_wasm_bindgen::to_value(&ret)?;
+  Ok(ret)
+}
+
+#[wasm_bindgen(js_name = findNodesStream)]
+pub fn find_nodes_stream(
+  src: String,
+  configs: Vec<JsValue>,
+  page: JsValue,
+  callback: MatchCallback,
+) -> Result<JsValue, JsError> {
+  let lang = WasmLang::get_current();
+  let rules = try_get_rule_configs(configs)?;
+  let page: Option<Page> = from_js_val(page)?;
+  let mut paginator = Paginator::new(page.unwrap_or_default());
+  let mut budget = Budget::new(Default::default());
+  let doc = WasmDoc::cached(src, lang)?;
   let root = AstGrep::doc(doc);
-  let diffs = combined.scan(&root, true).diffs;
-  if diffs.is_empty() {
-    return Ok(src);
+  let injections = extract_injections(&root)?;
+  let scan = RootScan::new(&root, &injections, &rules);
+  let ids: Vec<_> = scan.rule_ids().collect();
+  let mut error = None;
+  let mut stopped = false;
+  scan.scan_each(&mut budget, |rule, m| {
+    if paginator.accept(&rule.id) {
+      match callback.emit(&rule.id, &m) {
+        Ok(true) => {}
+        Ok(false) => stopped = true,
+        Err(e) => error = Some(e),
+      }
+    }
+    !stopped && error.is_none() && !paginator.is_full(ids.iter().copied())
+  });
+  if let Some(e) = error {
+    return Err(e.into());
+  }
+  if !stopped && paginator.is_full(ids.iter().copied()) {
+    budget.status = ScanStatus::LimitReached;
+  }
+  let summary = StreamSummary {
+    status: budget.status,
+    visited: budget.visited,
+    counts: paginator.counts,
+  };
+  let ret = serde_wasm_bindgen::to_value(&summary)?;
+  Ok(ret)
+}
+
+#[wasm_bindgen(js_name = fixErrors)]
+pub fn fix_errors(src: String, configs: Vec<JsValue>) -> Result<String, JsError> {
+  let rules = try_get_rule_configs(configs)?;
+  apply_fixes(src, &rules)
+}
+
+#[wasm_bindgen(js_name = replaceAll)]
+pub fn replace_all(src: String, config: JsValue) -> Result<JsValue, JsError> {
+  let rule = try_get_rule_config(config)?;
+  let ret = serde_wasm_bindgen::to_value(&replace_matches(src, rule)?)?;
+  Ok(ret)
+}
+
+/// fix every match of a single rule, with the rewriters fired in each match
+fn replace_matches(src: String, rule: RuleConfig<WasmLang>) -> Result<Replaced, JsError> {
+  let rules = [rule];
+  let options = FindOptions { trace_rewrites: true };
+  let mut found = find_matches(src.clone(), &rules, &options).map_err(|e| JsError::new(&e))?;
+  let matches = found.remove(&rules[0].id).unwrap_or_default();
+  let fixed = apply_fixes(src, &rules)?;
+  Ok(Replaced { fixed, matches })
+}
+
+#[wasm_bindgen(js_name = fixDiff)]
+pub fn fix_diff(src: String, configs: Vec<JsValue>, options: JsValue) -> Result<JsValue, JsError> {
+  let options: Option<DiffOptions> = from_js_val(options)?;
+  let rules = try_get_rule_configs(configs)?;
+  let edits = fix_edits(&src, WasmLang::get_current(), &rules)?;
+  let diff = FixDiff::new(&src, &edits, &options.unwrap_or_default());
+  let ret = serde_wasm_bindgen::to_value(&diff)?;
+  Ok(ret)
+}
+
+#[wasm_bindgen(js_name = renderReport)]
+pub fn render_report(src: String, configs: Vec<JsValue>, options: JsValue) -> Result<String, JsError> {
+  let options: Option<ReportOptions> = from_js_val(options)?;
+  let options = options.unwrap_or_default();
+  let rules = try_get_rule_configs(configs)?;
+  let matches = find_matches(src.clone(), &rules, &Default::default()).map_err(|e| JsError::new(&e))?;
+  let edits = fix_edits(&src, WasmLang::get_current(), &rules)?;
+  Ok(Report::new(&src, &options).render(&rules, &matches, &edits))
+}
+
+fn apply_fixes(src: String, rules: &[RuleConfig<WasmLang>]) -> Result<String, JsError> {
+  let edits = fix_edits(&src, WasmLang::get_current(), rules)?;
+  Ok(edited_source(src, &edits))
+}
+
+fn edited_source(src: String, edits: &[RuleEdit]) -> String {
+  if edits.is_empty() {
+    return src;
   }
-  let mut start = 0;
   let src: Vec<_> = src.chars().collect();
-  let mut new_content = Vec::<char>::new();
-  for (rule, nm) in diffs {
-    let range = nm.range();
-    if start > range.start {
-      continue;
+  apply_edits(&src, edits).into_iter().collect()
+}
+
+/// edits of the host and injected documents by position, without overlapping edits
+fn fix_edits(
+  src: &str,
+  lang: WasmLang,
+  rules: &[RuleConfig<WasmLang>],
+) -> Result<Vec<RuleEdit>, JsError> {
+  let doc = WasmDoc::try_new(src.to_string(), lang)?;
+  let mut edits = root_edits(&AstGrep::doc(doc), rules).map_err(|e| JsError::new(&e))?;
+  edits.sort_by_key(|(_, e)| e.position);
+  let mut start = 0;
+  edits.retain(|(_, edit)| {
+    if start > edit.position {
+      return false;
     }
-    let fixers = rule.get_fixer()?;
-    let fixer = fixers.first().expect("rule returned by diff must have fixer");
-    let edit = nm.make_edit(&rule.matcher, fixer);
+    start = edit.position + edit.deleted_length;
+    true
+  });
+  Ok(edits)
+}
+
+fn apply_edits(src: &[char], edits: &[RuleEdit]) -> Vec<char> {
+  let mut start = 0;
+  let mut new_content = Vec::<char>::new();
+  for (_, edit) in edits {
     new_content.extend(&src[start..edit.position]);
     new_content.extend(&edit.inserted_text);
     start = edit.position + edit.deleted_length;
   }
   // add trailing statements
   new_content.extend(&src[start..]);
-  Ok(new_content.into_iter().collect())
+  new_content
+}
+
+/// a fix edit with the id of the rule producing it, placed by char offset
+type RuleEdit = (String, Edit<Wrapper>);
+
+/// fix edits of the host and injected documents, which may overlap
+fn root_edits(
+  root: &AstGrep<WasmDoc>,
+  rules: &[RuleConfig<WasmLang>],
+) -> Result<Vec<RuleEdit>, String> {
+  let mut edits = collect_edits(root, rules, None)?;
+  for injection in extract_injections(root).map_err(error_message)? {
+    edits.extend(collect_edits(&injection.root, rules, Some(&injection))?);
+  }
+  to_char_offsets(root.root().get_doc().chars(), &mut edits);
+  Ok(edits)
+}
+
+/// ast-grep places edits by node range, in UTF-16 code units
+fn to_char_offsets(chars: &[char], edits: &mut [RuleEdit]) {
+  let mut units = vec![0];
+  units.extend(chars.iter().scan(0, |len, c| {
+    *len += c.len_utf16();
+    Some(*len)
+  }));
+  let char_offset = |unit: usize| units.partition_point(|&u| u < unit);
+  for (_, edit) in edits {
+    let start = char_offset(edit.position);
+    let end = char_offset(edit.position + edit.deleted_length);
+    edit.position = start;
+    edit.deleted_length = end - start;
+  }
 }
 
-fn convert_to_debug_node(n: Node) -> DumpNode {
-  let mut cursor = n.get_inner_node().0.walk();
-  let mut target = vec![];
-  dump_one_node(&mut cursor, &mut target);
-  target.pop().expect_throw("found empty node")
+/// like `scan_matches`, rules in other languages are skipped.
+/// `fixErrors` used to run every rule against the host tree whatever its language.
+fn collect_edits(
+  root: &AstGrep<WasmDoc>,
+  rules: &[RuleConfig<WasmLang>],
+  injection: Option<&Injection>,
+) -> Result<Vec<RuleEdit>, String> {
+  let lang = *root.root().lang();
+  let rules: Vec<_> = rules.iter().filter(|r| r.language == lang).collect();
+  if rules.is_empty() {
+    return Ok(vec![]);
+  }
+  let combined = CombinedScan::new(rules);
+  let mut edits = vec![];
+  for (rule, nm) in combined.scan(root, true).diffs {
+    let fixers = rule.get_fixer().map_err(error_message)?;
+    let fixer = fixers.first().expect("rule returned by diff must have fixer");
+    let mut edit = nm.make_edit(&rule.matcher, fixer);
+    if let Some(injection) = injection {
+      edit.position = injection.map_offset(edit.position);
+    }
+    edits.push((rule.id.clone(), edit));
+  }
+  Ok(edits)
 }
 
 #[wasm_bindgen(js_name = dumpASTNodes)]
-pub fn dump_ast_nodes(src: String) -> Result<JsValue, JsError> {
+pub fn dump_ast_nodes(src: String, options: JsValue) -> Result<JsValue, JsError> {
+  let options: Option<DumpOptions> = from_js_val(options)?;
+  let debug_node = dump_ast(src, &options.unwrap_or_default())?;
+  let ret = serde_wasm_bindgen::to_value(&debug_node)?;
+  Ok(ret)
+}
+
+#[wasm_bindgen(js_name = dumpASTText)]
+pub fn dump_ast_text(src: String, options: JsValue) -> Result<String, JsError> {
+  let options: Option<RenderOptions> = from_js_val(options)?;
   let lang = WasmLang::get_current();
   let doc = WasmDoc::try_new(src, lang)?;
   let root = AstGrep::doc(doc);
-  let debug_node = convert_to_debug_node(root.root());
-  let ret = serde_wasm_bindgen::to_value(&debug_node)?;
+  let mut cursor = root.root().get_inner_node().0.walk();
+  Ok(render_tree(&mut cursor, &options.unwrap_or_default()))
+}
+
+#[wasm_bindgen(js_name = parseDiagnostics)]
+pub fn parse_diagnostics(src: String) -> Result<JsValue, JsError> {
+  let lang = WasmLang::get_current();
+  let doc = WasmDoc::try_new(src.clone(), lang)?;
+  let root = AstGrep::doc(doc);
+  let diagnostics = syntax_diagnostics(&root.root().get_inner_node().0, &src);
+  let ret = serde_wasm_bindgen::to_value(&diagnostics)?;
   Ok(ret)
 }
 
@@ -109,17 +426,593 @@ pub fn dump_pattern(src: String, selector: Option<String>) -> Result<JsValue, Js
   Ok(ret)
 }
 
+#[wasm_bindgen(js_name = inspectPosition)]
+pub fn inspect_position(src: String, configs: Vec<JsValue>, query: JsValue) -> Result<JsValue, JsError> {
+  let query: CursorQuery = from_js_val(query)?;
+  let rules = try_get_rule_configs(configs)?;
+  let info = inspect_position_impl(src, WasmLang::get_current(), &rules, &query)?;
+  let ret = serde_wasm_bindgen::to_value(&info)?;
+  Ok(ret)
+}
+
+#[wasm_bindgen(js_name = structuralDiff)]
+pub fn structural_diff(before: String, after: String) -> Result<JsValue, JsError> {
+  let lang = WasmLang::get_current();
+  let before = AstGrep::doc(WasmDoc::try_new(before, lang)?);
+  let after = AstGrep::doc(WasmDoc::try_new(after, lang)?);
+  let changes = structural_diff_impl(before.root(), after.root());
+  let ret = serde_wasm_bindgen::to_value(&changes)?;
+  Ok(ret)
+}
+
+#[wasm_bindgen(js_name = dumpGrammar)]
+pub fn dump_grammar() -> Result<JsValue, JsError> {
+  let grammar = Grammar::of(WasmLang::get_current())?;
+  let ret = serde_wasm_bindgen::to_value(&*grammar)?;
+  Ok(ret)
+}
+
+#[wasm_bindgen(js_name = checkRuleNames)]
+pub fn check_rule_names(config: JsValue) -> Result<JsValue, JsError> {
+  let unknown = unknown_rule_names(&from_js_val(config)?).map_err(|e| JsError::new(&e))?;
+  let ret = serde_wasm_bindgen::to_value(&unknown)?;
+  Ok(ret)
+}
+
+/// unknown names in the grammar of the rule's language,
+/// a rule being edited without a language is checked against the current one
+fn unknown_rule_names(config: &serde_json::Value) -> Result<Vec<UnknownName>, String> {
+  let lang = match config.get("language") {
+    Some(lang) => serde_json::from_value(lang.clone()).map_err(error_message)?,
+    None => WasmLang::get_current(),
+  };
+  let grammar = Grammar::of(lang).map_err(error_message)?;
+  Ok(grammar.find_unknown_names(config))
+}
+
+#[wasm_bindgen(js_name = checkRules)]
+pub fn check_rules(configs: Vec<JsValue>, options: JsValue) -> Result<JsValue, JsError> {
+  let options: Option<CheckOptions> = from_js_val(options)?;
+  let values = configs.into_iter().map(from_js_val).collect::<Result<Vec<_>, _>>()?;
+  let check = check_rule_values(values, &options.unwrap_or_default()).map_err(|e| JsError::new(&e))?;
+  let ret = serde_wasm_bindgen::to_value(&check)?;
+  Ok(ret)
+}
+
+/// compile rules, in lenient mode unknown names are returned as warnings
+/// and rules with them are not compiled, ast-grep rejects them too
+fn check_rule_values(
+  values: Vec<serde_json::Value>,
+  options: &CheckOptions,
+) -> Result<RuleCheck, String> {
+  let mut warnings = vec![];
+  for value in values {
+    let config: SerializableRuleConfig<WasmLang> =
+      serde_json::from_value(value.clone()).map_err(error_message)?;
+    let found = match Grammar::of(config.language) {
+      Ok(grammar) => grammar.validate(&value, options).map_err(error_message)?,
+      Err(_) => vec![],
+    };
+    if found.is_empty() {
+      RuleConfig::try_from(config, &Default::default()).map_err(error_message)?;
+    }
+    warnings.extend(found);
+  }
+  Ok(RuleCheck { warnings })
+}
+
+#[wasm_bindgen(js_name = dumpASTGraph)]
+pub fn dump_ast_graph(src: String, options: JsValue) -> Result<String, JsError> {
+  let options: Option<GraphOptions> = from_js_val(options)?;
+  let options = options.unwrap_or_default();
+  let dumped = dump_ast(src, &Default::default())?;
+  Ok(render_graph(&dumped.to_graph(&options, 0), options.format))
+}
+
+#[wasm_bindgen(js_name = suggestSelectors)]
+pub fn suggest_selectors(src: String, range: JsValue) -> Result<JsValue, JsError> {
+  let candidates = suggest_selectors_impl(src, from_js_val(range)?)?;
+  let ret = serde_wasm_bindgen::to_value(&candidates)?;
+  Ok(ret)
+}
+
+#[wasm_bindgen(js_name = patternFromSelection)]
+pub fn pattern_from_selection(src: String, selection: JsValue) -> Result<JsValue, JsError> {
+  let generated = pattern_from_selection_impl(src, from_js_val(selection)?)?;
+  let ret = serde_wasm_bindgen::to_value(&generated)?;
+  Ok(ret)
+}
+
+#[wasm_bindgen(js_name = inferRule)]
+pub fn infer_rule(examples: JsValue) -> Result<JsValue, JsError> {
+  let inferred = infer_rule_impl(from_js_val(examples)?)?;
+  let ret = serde_wasm_bindgen::to_value(&inferred)?;
+  Ok(ret)
+}
+
+#[wasm_bindgen(js_name = dumpPatternGraph)]
+pub fn dump_pattern_graph(
+  src: String,
+  selector: Option<String>,
+  options: JsValue,
+) -> Result<String, JsError> {
+  let options: Option<GraphOptions> = from_js_val(options)?;
+  let options = options.unwrap_or_default();
+  let dumped = dump_pattern_impl(src, selector)?;
+  Ok(render_graph(&dumped.to_graph(&options, 0), options.format))
+}
+
+/// a language server for web workers, exchanging JSON-RPC messages as strings
+#[wasm_bindgen]
+pub struct LanguageServer(Server);
+
+#[wasm_bindgen]
+impl LanguageServer {
+  #[wasm_bindgen(constructor)]
+  pub fn new(configs: Vec<JsValue>) -> Result<LanguageServer, JsError> {
+    Ok(Self(Server::new(try_get_rule_configs(configs)?)))
+  }
+
+  /// replace the rules, returning diagnostics of open documents as a JSON array
+  #[wasm_bindgen(js_name = setRules)]
+  pub fn set_rules(&mut self, configs: Vec<JsValue>) -> Result<String, JsError> {
+    let messages = self.0.set_rules(try_get_rule_configs(configs)?);
+    Ok(serde_json::Value::Array(messages).to_string())
+  }
+
+  /// handle one message, returning the messages to send back as a JSON array
+  #[wasm_bindgen(js_name = handleMessage)]
+  pub fn handle_message(&mut self, message: &str) -> String {
+    serde_json::Value::Array(self.0.handle(message)).to_string()
+  }
+}
+
+#[wasm_bindgen(js_name = encodeShareState)]
+pub fn encode_share_state(state: JsValue) -> Result<String, JsError> {
+  let state: ShareState = from_js_val(state)?;
+  Ok(encode_state(&state)?)
+}
+
+#[wasm_bindgen(js_name = decodeShareState)]
+pub fn decode_share_state(encoded: String) -> Result<JsValue, JsError> {
+  let state = decode_state(&encoded)?;
+  let ret = serde_wasm_bindgen::to_value(&state)?;
+  Ok(ret)
+}
+
+#[wasm_bindgen(js_name = validateShareState)]
+pub fn validate_share_state(state: JsValue) -> Result<JsValue, JsError> {
+  let state: ShareState = from_js_val(state)?;
+  let ret = serde_wasm_bindgen::to_value(&validate_state(&state))?;
+  Ok(ret)
+}
+
+#[wasm_bindgen(js_name = runShareState)]
+pub fn run_share_state(state: JsValue) -> Result<JsValue, JsError> {
+  let state: ShareState = from_js_val(state)?;
+  let ret = serde_wasm_bindgen::to_value(&run_state(&state)?)?;
+  Ok(ret)
+}
+
+/// scan the source of a share state with its own language and rules
+fn run_state(state: &ShareState) -> Result<ShareScan, JsError> {
+  let lang = WasmLang::from_str(&state.lang)?;
+  if !lang.is_loaded() {
+    return Err(JsError::new(&format!("Parser for {} is not loaded.", lang)));
+  }
+  let rules = state
+    .rule_values()?
+    .into_iter()
+    .map(parse_rule_config)
+    .collect::<Result<Vec<_>, _>>()?;
+  let doc = WasmDoc::try_new(state.source.clone(), lang)?;
+  let root = AstGrep::doc(doc);
+  let matches = root_matches(&root, &rules, &Default::default()).map_err(|e| JsError::new(&e))?;
+  let edits = fix_edits(&state.source, lang, &rules)?;
+  let fixed = edited_source(state.source.clone(), &edits);
+  Ok(ShareScan { matches, fixed })
+}
+
+fn try_get_rule_configs(configs: Vec<JsValue>) -> Result<Vec<RuleConfig<WasmLang>>, JsError> {
+  configs.into_iter().map(try_get_rule_config).collect()
+}
+
 fn try_get_rule_config(config: JsValue) -> Result<RuleConfig<WasmLang>, JsError> {
-  let config: SerializableRuleConfig<WasmLang> = from_js_val(config)?;
-  RuleConfig::try_from(config, &Default::default()).map_err(dump_error)
+  parse_rule_config(from_js_val(config)?)
 }
 
-fn dump_error(err: impl Error) -> JsError {
+fn parse_rule_config(value: serde_json::Value) -> Result<RuleConfig<WasmLang>, JsError> {
+  rule_from_value(value).map_err(|e| JsError::new(&e))
+}
+
+/// same as `parse_rule_config` but keeps the error message,
+/// which is not readable from a `JsError`
+fn rule_from_value(value: serde_json::Value) -> Result<RuleConfig<WasmLang>, String> {
+  let config: SerializableRuleConfig<WasmLang> =
+    serde_json::from_value(value.clone()).map_err(error_message)?;
+  // rules for a language that is not loaded fail to compile below
+  if let Ok(grammar) = Grammar::of(config.language) {
+    grammar.validate(&value, &Default::default()).map_err(error_message)?;
+  }
+  RuleConfig::try_from(config, &Default::default()).map_err(error_message)
+}
+
+fn error_message(err: impl Error) -> String {
   let mut errors = vec![err.to_string()];
   let mut err: &dyn Error = &err;
   while let Some(e) = err.source() {
     errors.push(e.to_string());
     err = e;
   }
-  JsError::new(&format!("{}", errors.join("\n")))
-}
\ No newline at end of file
+  errors.join("\n")
+}
+
+#[cfg(all(test, feature = "native"))]
+mod test {
+  use super::*;
+  use serde_json::{json, Value};
+  use std::sync::{Mutex, MutexGuard};
+
+  // the current language is global state shared by all tests
+  static LOCK: Mutex<()> = Mutex::new(());
+
+  pub fn use_langs(langs: &[WasmLang]) -> MutexGuard<'static, ()> {
+    let guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    for &lang in langs {
+      WasmLang::set_native(lang).expect("native grammar should load");
+    }
+    guard
+  }
+
+  fn rule(config: Value) -> RuleConfig<WasmLang> {
+    rule_from_value(config).expect("rule should be valid")
+  }
+
+  fn log_rule(lang: &str) -> RuleConfig<WasmLang> {
+    rule(json!({
+      "id": "no-log",
+      "language": lang,
+      "message": "remove log",
+      "rule": { "pattern": "console.log($A)" },
+      "fix": "logger.log($A)",
+    }))
+  }
+
+  #[test]
+  fn test_find_nodes() {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let src = "console.log(a)\nfoo(b)\nconsole.log(中)";
+    let found = find_matches(src.into(), &[log_rule("javascript")], &Default::default()).expect("should parse");
+    let matches = &found["no-log"];
+    assert_eq!(matches.len(), 2);
+    assert_eq!(matches[0].node.text, "console.log(a)");
+    assert_eq!(matches[0].node.range, (0, 0, 0, 14));
+    assert_eq!(matches[0].message, "remove log");
+    assert_eq!(matches[1].env["A"].text, "中");
+    assert_eq!(matches[1].node.range, (2, 0, 2, 14));
+  }
+
+  #[test]
+  fn test_find_nodes_suppressed() {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let src = "console.log(a) // ast-grep-ignore: no-log\nconsole.log(b)";
+    let found = find_matches(src.into(), &[log_rule("javascript")], &Default::default()).expect("should parse");
+    let matches = &found["no-log"];
+    assert_eq!(matches.len(), 1);
+    assert_eq!(matches[0].node.text, "console.log(b)");
+  }
+
+  #[test]
+  fn test_find_nodes_in_injection() {
+    let _lock = use_langs(&[WasmLang::JavaScript, WasmLang::Html]);
+    let src = "<div></div>\n<script>console.log(a)</script>";
+    let found = find_matches(src.into(), &[log_rule("javascript")], &Default::default()).expect("should parse");
+    let matches = &found["no-log"];
+    assert_eq!(matches.len(), 1);
+    assert_eq!(matches[0].node.range, (1, 8, 1, 22));
+  }
+
+  #[test]
+  fn test_fix_errors() {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let src = "console.log(a); foo(); console.log(b, c)";
+    let fixed = apply_fixes(src.into(), &[log_rule("javascript")]).unwrap_or_default();
+    assert_eq!(fixed, "logger.log(a); foo(); console.log(b, c)");
+  }
+
+  #[test]
+  fn test_fix_in_injection() {
+    let _lock = use_langs(&[WasmLang::JavaScript, WasmLang::Html]);
+    let src = "<p>console.log(a)</p>\n<script>console.log(b)</script>";
+    let fixed = apply_fixes(src.into(), &[log_rule("javascript")]).unwrap_or_default();
+    assert_eq!(fixed, "<p>console.log(a)</p>\n<script>logger.log(b)</script>");
+  }
+
+  #[test]
+  fn test_fix_other_language() {
+    let _lock = use_langs(&[WasmLang::JavaScript, WasmLang::Python]);
+    let fix = rule(json!({
+      "id": "print",
+      "language": "python",
+      "rule": { "pattern": "print($A)" },
+      "fix": "log($A)",
+    }));
+    let src = "print(x)\nprint(y)";
+    let fixed = apply_fixes(src.into(), &[fix, log_rule("javascript")]).unwrap_or_default();
+    assert_eq!(fixed, "log(x)\nlog(y)");
+  }
+
+  #[test]
+  fn test_rule_unloaded_language() {
+    // there is no native go grammar, so it is never loaded
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let pattern = rule_from_value(json!({
+      "id": "print",
+      "language": "go",
+      "rule": { "pattern": "fmt.Println($A)" },
+    }));
+    let err = pattern.err().expect("rule should fail");
+    assert!(err.contains("Language `go` is not loaded"), "{}", err);
+    let kind = rule_from_value(json!({
+      "id": "call",
+      "language": "go",
+      "rule": { "kind": "call_expression" },
+    }));
+    assert!(kind.is_err());
+  }
+
+  #[test]
+  fn test_check_rules() {
+    let _lock = use_langs(&[WasmLang::JavaScript, WasmLang::Python]);
+    let typo = json!({
+      "id": "typo",
+      "language": "javascript",
+      "rule": { "kind": "identifier", "inside": { "kind": "call_expresion" } },
+    });
+    let err = check_rule_values(vec![typo.clone()], &CheckOptions::default()).err();
+    let err = err.expect("strict check should fail");
+    assert!(err.contains("Did you mean `call_expression`?"), "{}", err);
+    let lenient = CheckOptions { lenient: true };
+    let field = json!({
+      "id": "field",
+      "language": "javascript",
+      "rule": { "kind": "identifier", "inside": { "kind": "call_expression", "field": "fuction" } },
+    });
+    let check = check_rule_values(vec![typo, field], &lenient).expect("names should only warn");
+    assert_eq!(
+      check.warnings,
+      [
+        "Rule `typo`: Unknown kind `call_expresion`. Did you mean `call_expression`?",
+        "Rule `field`: Unknown field `fuction`. Did you mean `function`?",
+      ]
+    );
+    // other errors still fail in lenient mode
+    let invalid = json!({ "id": "invalid", "language": "javascript", "rule": { "pattern": "" } });
+    assert!(check_rule_values(vec![invalid], &lenient).is_err());
+  }
+
+  #[test]
+  fn test_check_rule_names_language() {
+    // the current language is python, the rule is checked as javascript
+    let _lock = use_langs(&[WasmLang::JavaScript, WasmLang::Python]);
+    let config = json!({
+      "id": "js",
+      "language": "javascript",
+      "rule": { "kind": "arrow_function", "has": { "kind": "lambda" } },
+    });
+    let unknown = unknown_rule_names(&config).expect("language should be loaded");
+    let names: Vec<_> = unknown.iter().map(|u| u.name.as_str()).collect();
+    assert_eq!(names, ["lambda"]);
+  }
+
+  #[test]
+  fn test_run_share_state() {
+    let _lock = use_langs(&[WasmLang::Python, WasmLang::JavaScript]);
+    let state = ShareState {
+      lang: "python".into(),
+      query: "print($A)".into(),
+      rewrite: "log($A)".into(),
+      source: "print(1)\nprint(2)".into(),
+      ..Default::default()
+    };
+    let encoded = encode_state(&state).expect("should encode");
+    let decoded = decode_state(&encoded).expect("should decode");
+    let scan = run_state(&decoded).unwrap_or_else(|_| panic!("state should run"));
+    assert_eq!(scan.matches["test-rule"].len(), 2);
+    assert_eq!(scan.fixed, "log(1)\nlog(2)");
+  }
+
+  const TRACE: FindOptions = FindOptions { trace_rewrites: true };
+
+  fn unwrap_rule() -> RuleConfig<WasmLang> {
+    let unwrap = |var: &str| json!({ "rewrite": { "source": var, "rewriters": ["unwrap"] } });
+    rule(json!({
+      "id": "unwrap-all",
+      "language": "javascript",
+      "rule": { "pattern": "wrap($A)" },
+      "transform": { "NEW": unwrap("$A") },
+      "fix": "$NEW",
+      "rewriters": [{
+        "id": "unwrap",
+        "rule": { "pattern": "wrap($X)" },
+        "transform": { "INNER": unwrap("$X") },
+        "fix": "$INNER",
+      }],
+    }))
+  }
+
+  #[test]
+  fn test_rewriters() {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let src = "wrap(wrap(wrap(1)))";
+    let found = find_matches(src.into(), &[unwrap_rule()], &TRACE).expect("should parse");
+    let outer = &found["unwrap-all"][0];
+    assert_eq!(outer.env["NEW"].text, "1");
+    let trace = &outer.rewrites;
+    assert_eq!(trace.len(), 1);
+    assert_eq!((trace[0].variable.as_str(), trace[0].rewriter.as_str()), ("NEW", "unwrap"));
+    assert_eq!(trace[0].node.range, (0, 5, 0, 18));
+    assert_eq!(trace[0].replacement, "1");
+    let nested = &trace[0].nested;
+    assert_eq!(nested.len(), 1);
+    assert_eq!(nested[0].variable, "INNER");
+    assert_eq!(nested[0].node.text, "wrap(1)");
+    assert!(nested[0].nested.is_empty());
+    let fixed = apply_fixes(src.into(), &[unwrap_rule()]).unwrap_or_default();
+    assert_eq!(fixed, "1");
+  }
+
+  #[test]
+  fn test_rewriters_join_by() {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let rule = rule(json!({
+      "id": "double-args",
+      "language": "javascript",
+      "rule": { "pattern": "f($$$ARGS)" },
+      "transform": { "NEW": "rewrite($$$ARGS, rewriters=[double], joinBy=', ')" },
+      "fix": "f($NEW)",
+      // a rewriter without kinds
+      "rewriters": [{ "id": "double", "rule": { "pattern": "$A" }, "fix": "$A + $A" }],
+    }));
+    let found = find_matches("f(a, b)".into(), &[rule], &TRACE).expect("should parse");
+    let trace = &found["double-args"][0].rewrites;
+    let replaced: Vec<_> = trace.iter().map(|t| t.replacement.as_str()).collect();
+    assert_eq!(replaced, ["a + a", "b + b"]);
+  }
+
+  #[test]
+  fn test_rewriters_in_injection() {
+    let _lock = use_langs(&[WasmLang::JavaScript, WasmLang::Html]);
+    let src = "<p></p>\n<script>wrap(wrap(2))</script>";
+    let found = find_matches(src.into(), &[unwrap_rule()], &TRACE).expect("should parse");
+    let trace = &found["unwrap-all"][0].rewrites;
+    assert_eq!(trace[0].node.range, (1, 13, 1, 20));
+    let fixed = apply_fixes(src.into(), &[unwrap_rule()]).unwrap_or_default();
+    assert_eq!(fixed, "<p></p>\n<script>2</script>");
+  }
+
+  #[test]
+  fn test_rewriters_opt_in() {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let found = find_matches("wrap(wrap(1))".into(), &[unwrap_rule()], &Default::default());
+    let found = found.expect("should parse");
+    assert_eq!(found["unwrap-all"][0].env["NEW"].text, "1");
+    assert!(found["unwrap-all"][0].rewrites.is_empty());
+  }
+
+  #[test]
+  fn test_replace_all() {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let rule = rule(json!({
+      "id": "rename-args",
+      "language": "javascript",
+      "rule": { "pattern": "f($$$ARGS)" },
+      "transform": { "NEW": { "rewrite": { "source": "$$$ARGS", "rewriters": ["rename"] } } },
+      "fix": "g($NEW)",
+      "rewriters": [{ "id": "rename", "rule": { "pattern": "a" }, "fix": "b" }],
+    }));
+    let replaced = replace_matches("f(a, c, a)".into(), rule).unwrap_or_else(|_| panic!("should replace"));
+    assert_eq!(replaced.fixed, "g(b, c, b)");
+    let trace = &replaced.matches[0].rewrites;
+    assert_eq!(trace.len(), 2);
+    assert_eq!(trace[1].node.range, (0, 8, 0, 9));
+  }
+
+  fn find_meta_var(tree: &Value) -> Option<&Value> {
+    if tree["pattern"] == "metaVar" {
+      return Some(tree);
+    }
+    tree["children"].as_array()?.iter().find_map(find_meta_var)
+  }
+
+  fn dump(src: &str, options: Value) -> Value {
+    let options: DumpOptions = serde_json::from_value(options).expect("options should be valid");
+    let dumped = dump_ast(src.into(), &options).unwrap_or_else(|_| panic!("source should parse"));
+    serde_json::to_value(&dumped).expect("should serialize")
+  }
+
+  #[test]
+  fn test_dump_range() {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let src = "let a = 1;\nfoo(b)";
+    let full = dump(src, json!({}));
+    let dumped = dump(src, json!({ "startIndex": 4, "endIndex": 9, "namedOnly": true }));
+    assert_eq!(dumped["kind"], "variable_declarator");
+    assert_eq!(dumped["id"], full["children"][0]["children"][1]["id"]);
+    let dumped = dump(src, json!({ "start": { "row": 1, "column": 4 }, "namedOnly": true }));
+    assert_eq!(dumped["kind"], "identifier");
+    assert_eq!(dumped["text"], "b");
+    assert_eq!(dumped["id"], full["children"][1]["children"][0]["children"][1]["children"][1]["id"]);
+  }
+
+  #[test]
+  fn test_dump_max_depth() {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let dumped = dump("let a = 1;\nfoo(b)", json!({ "maxDepth": 1 }));
+    let children = dumped["children"].as_array().expect("should have children");
+    let kinds: Vec<_> = children.iter().map(|c| &c["kind"]).collect();
+    assert_eq!(kinds, ["lexical_declaration", "expression_statement"]);
+    assert!(children.iter().all(|c| c["collapsed"] == true));
+    assert_eq!(children[1]["children"], json!([]));
+  }
+
+  #[test]
+  fn test_dump_expand_node() {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let src = "let a = 1;\nfoo(b)";
+    let collapsed = dump(src, json!({ "maxDepth": 1 }));
+    let id = &collapsed["children"][1]["id"];
+    let dumped = dump(src, json!({ "nodeId": id, "maxDepth": 1 }));
+    assert_eq!(dumped["kind"], "expression_statement");
+    assert_eq!(&dumped["id"], id);
+    assert_eq!(dumped["children"][0]["kind"], "call_expression");
+    assert_eq!(dumped["children"][0]["collapsed"], true);
+    // ids do not depend on the options of the dump
+    let dumped = dump(src, json!({ "namedOnly": true }));
+    assert_eq!(&dumped["children"][1]["id"], id);
+  }
+
+  #[test]
+  fn test_match_id() {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let src = "let a = 1;\nconsole.log(b)";
+    let found = find_matches(src.into(), &[log_rule("javascript")], &Default::default()).expect("should parse");
+    let dumped = dump(src, json!({ "namedOnly": true }));
+    let call = &dumped["children"][1]["children"][0];
+    assert_eq!(call["kind"], "call_expression");
+    assert_eq!(call["id"], found["no-log"][0].id);
+  }
+
+  #[test]
+  fn test_dump_pattern() {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let dumped = dump_pattern_impl("foo($A)".into(), None)
+      .unwrap_or_else(|_| panic!("pattern should be valid"));
+    let dumped = serde_json::to_value(&dumped).expect("should serialize");
+    assert_eq!(dumped["kind"], "program");
+    assert_eq!(dumped["rootKinds"], json!(["call_expression"]));
+    let meta_var = find_meta_var(&dumped).expect("should have meta var");
+    assert_eq!(meta_var["text"], "$A");
+  }
+
+  #[test]
+  fn test_dump_pattern_with_selector() {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let dumped = dump_pattern_impl("class A { $F = 1 }".into(), Some("field_definition".into()))
+      .unwrap_or_else(|_| panic!("pattern should be valid"));
+    let dumped = serde_json::to_value(&dumped).expect("should serialize");
+    assert_eq!(dumped["rootKinds"], json!(["field_definition"]));
+  }
+
+  #[test]
+  fn test_dump_pattern_diagnostics() {
+    // python replaces `$` of metavariables with an expando char
+    let _lock = use_langs(&[WasmLang::Python]);
+    let dumped = dump_pattern_impl("foo($A, )) + $B".into(), None)
+      .unwrap_or_else(|_| panic!("pattern should be valid"));
+    let dumped = serde_json::to_value(&dumped).expect("should serialize");
+    let diagnostics = dumped["diagnostics"].as_array().expect("should report errors");
+    assert!(!diagnostics.is_empty());
+    assert_eq!(diagnostics[0]["context"], "foo($A, )) + $B");
+  }
+}
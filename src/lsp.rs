@@ -0,0 +1,620 @@
+//! A language server speaking JSON-RPC messages without a transport,
+//! so it can run in a web worker and be driven directly by tests.
+
+use crate::utils::WasmMatch;
+use crate::wasm_lang::{WasmDoc, WasmLang, Wrapper};
+use crate::{root_edits, root_matches};
+use ast_grep_config::{RuleConfig, Severity};
+use ast_grep_core::source::{Doc, Edit};
+use ast_grep_core::AstGrep;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+
+#[derive(Deserialize)]
+struct Message {
+  id: Option<Value>,
+  method: Option<String>,
+  #[serde(default)]
+  params: Value,
+}
+
+#[derive(Deserialize)]
+struct Position {
+  line: usize,
+  character: usize,
+}
+
+#[derive(Deserialize)]
+struct Range {
+  start: Position,
+  end: Position,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TextDocumentItem {
+  uri: String,
+  language_id: String,
+  version: i64,
+  text: String,
+}
+
+#[derive(Deserialize)]
+struct DocumentId {
+  uri: String,
+  #[serde(default)]
+  version: i64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DidOpenParams {
+  text_document: TextDocumentItem,
+}
+
+#[derive(Deserialize)]
+struct ContentChange {
+  range: Option<Range>,
+  text: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DidChangeParams {
+  text_document: DocumentId,
+  content_changes: Vec<ContentChange>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DocumentParams {
+  text_document: DocumentId,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CodeActionParams {
+  text_document: DocumentId,
+  range: Range,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HoverParams {
+  text_document: DocumentId,
+  position: Position,
+}
+
+struct RpcError {
+  code: i64,
+  message: String,
+}
+
+impl RpcError {
+  fn new(code: i64, message: impl Into<String>) -> Self {
+    Self {
+      code,
+      message: message.into(),
+    }
+  }
+}
+
+/// how `Position.character` counts, negotiated in `initialize`
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+  Utf16,
+  Utf32,
+}
+
+/// converts between char offsets and LSP positions
+struct LineIndex<'a> {
+  chars: &'a [char],
+  starts: Vec<usize>,
+  encoding: Encoding,
+}
+
+impl<'a> LineIndex<'a> {
+  fn new(chars: &'a [char], encoding: Encoding) -> Self {
+    let mut starts = vec![0];
+    let newlines = chars.iter().enumerate().filter(|(_, c)| **c == '\n');
+    starts.extend(newlines.map(|(i, _)| i + 1));
+    Self {
+      chars,
+      starts,
+      encoding,
+    }
+  }
+
+  /// char offset of a node position, whose column counts UTF-16 code units like tree-sitter
+  fn offset(&self, line: usize, column: usize) -> usize {
+    self.offset_in_line(line, column, char::len_utf16)
+  }
+
+  fn offset_of(&self, pos: &Position) -> usize {
+    self.offset_in_line(pos.line, pos.character, |c| self.width(c))
+  }
+
+  fn offset_in_line(&self, line: usize, column: usize, width: impl Fn(char) -> usize) -> usize {
+    let Some(&start) = self.starts.get(line) else {
+      return self.chars.len();
+    };
+    let mut units = 0;
+    let mut offset = start;
+    for &c in &self.chars[start..] {
+      if c == '\n' || units >= column {
+        break;
+      }
+      units += width(c);
+      offset += 1;
+    }
+    offset
+  }
+
+  fn position(&self, line: usize, column: usize) -> Value {
+    let start = self.offset(line, 0);
+    let end = self.offset(line, column);
+    let character: usize = self.chars[start..end].iter().map(|&c| self.width(c)).sum();
+    json!({ "line": line, "character": character })
+  }
+
+  fn range(&self, range: (usize, usize, usize, usize)) -> Value {
+    let (start_line, start_column, end_line, end_column) = range;
+    json!({
+      "start": self.position(start_line, start_column),
+      "end": self.position(end_line, end_column),
+    })
+  }
+
+  fn width(&self, c: char) -> usize {
+    match self.encoding {
+      Encoding::Utf16 => c.len_utf16(),
+      Encoding::Utf32 => 1,
+    }
+  }
+}
+
+/// a rule match and the fix of the same rule starting at the match, if any
+struct Finding {
+  rule: usize,
+  node: WasmMatch,
+  fix: Option<(usize, usize, String)>,
+}
+
+struct Document {
+  version: i64,
+  doc: WasmDoc,
+  findings: Vec<Finding>,
+}
+
+pub struct Server {
+  rules: Vec<RuleConfig<WasmLang>>,
+  documents: HashMap<String, Document>,
+  encoding: Encoding,
+  shutdown: bool,
+}
+
+impl Server {
+  pub fn new(rules: Vec<RuleConfig<WasmLang>>) -> Self {
+    Self {
+      rules,
+      documents: HashMap::new(),
+      encoding: Encoding::Utf16,
+      shutdown: false,
+    }
+  }
+
+  /// replace the rules, returning diagnostics for every open document
+  pub fn set_rules(&mut self, rules: Vec<RuleConfig<WasmLang>>) -> Vec<Value> {
+    self.rules = rules;
+    let mut uris: Vec<_> = self.documents.keys().cloned().collect();
+    uris.sort();
+    let mut out = vec![];
+    for uri in uris {
+      if let Err(e) = self.rescan(&uri) {
+        out.push(log_message(1, &e.message));
+      }
+      out.push(self.publish(&uri));
+    }
+    out
+  }
+
+  /// handle one message, returning the response and notifications to send back
+  pub fn handle(&mut self, message: &str) -> Vec<Value> {
+    let message: Message = match serde_json::from_str(message) {
+      Ok(message) => message,
+      Err(e) => return vec![error_response(Value::Null, RpcError::new(PARSE_ERROR, e.to_string()))],
+    };
+    // responses to requests we never send
+    let Some(method) = message.method else {
+      return vec![];
+    };
+    let mut out = vec![];
+    let result = if self.shutdown && message.id.is_some() && method != "exit" {
+      Err(RpcError::new(INVALID_REQUEST, "Server is shutting down."))
+    } else {
+      self.dispatch(&method, message.params, &mut out)
+    };
+    match (message.id, result) {
+      (Some(id), Ok(result)) => out.insert(0, json!({ "jsonrpc": "2.0", "id": id, "result": result })),
+      (Some(id), Err(e)) => out.insert(0, error_response(id, e)),
+      (None, Err(e)) => out.push(log_message(1, &e.message)),
+      (None, Ok(_)) => {}
+    }
+    out
+  }
+
+  fn dispatch(&mut self, method: &str, params: Value, out: &mut Vec<Value>) -> Result<Value, RpcError> {
+    match method {
+      "initialize" => Ok(self.initialize(&params)),
+      "shutdown" => {
+        self.shutdown = true;
+        Ok(Value::Null)
+      }
+      "textDocument/didOpen" => self.did_open(parse_params(params)?, out),
+      "textDocument/didChange" => self.did_change(parse_params(params)?, out),
+      "textDocument/didClose" => {
+        let params: DocumentParams = parse_params(params)?;
+        if self.documents.remove(&params.text_document.uri).is_some() {
+          let uri = params.text_document.uri;
+          out.push(notification("textDocument/publishDiagnostics", json!({ "uri": uri, "diagnostics": [] })));
+        }
+        Ok(Value::Null)
+      }
+      "textDocument/codeAction" => Ok(self.code_action(parse_params(params)?)),
+      "textDocument/hover" => Ok(self.hover(parse_params(params)?)),
+      "initialized" | "exit" => Ok(Value::Null),
+      // `$/` notifications may be ignored
+      m if m.starts_with("$/") => Ok(Value::Null),
+      m => Err(RpcError::new(METHOD_NOT_FOUND, format!("Unknown method {}", m))),
+    }
+  }
+
+  fn initialize(&mut self, params: &Value) -> Value {
+    let encodings = &params["capabilities"]["general"]["positionEncodings"];
+    let utf32 = encodings.as_array().map_or(false, |e| e.contains(&json!("utf-32")));
+    self.encoding = if utf32 { Encoding::Utf32 } else { Encoding::Utf16 };
+    json!({
+      "capabilities": {
+        "positionEncoding": if utf32 { "utf-32" } else { "utf-16" },
+        // incremental
+        "textDocumentSync": { "openClose": true, "change": 2 },
+        "codeActionProvider": { "codeActionKinds": ["quickfix"] },
+        "hoverProvider": true,
+      },
+      "serverInfo": { "name": "ast-grep-wasm", "version": env!("CARGO_PKG_VERSION") },
+    })
+  }
+
+  fn did_open(&mut self, params: DidOpenParams, out: &mut Vec<Value>) -> Result<Value, RpcError> {
+    let item = params.text_document;
+    let lang = match language_of(&item.language_id) {
+      Some(lang) if lang.is_loaded() => lang,
+      _ => {
+        let message = format!("Parser for {} is not loaded, {} is not checked.", item.language_id, item.uri);
+        out.push(log_message(2, &message));
+        return Ok(Value::Null);
+      }
+    };
+    let doc = WasmDoc::try_new(item.text, lang).map_err(|e| RpcError::new(INTERNAL_ERROR, e.to_string()))?;
+    let document = Document {
+      version: item.version,
+      doc,
+      findings: vec![],
+    };
+    self.documents.insert(item.uri.clone(), document);
+    self.rescan(&item.uri)?;
+    out.push(self.publish(&item.uri));
+    Ok(Value::Null)
+  }
+
+  fn did_change(&mut self, params: DidChangeParams, out: &mut Vec<Value>) -> Result<Value, RpcError> {
+    let uri = params.text_document.uri;
+    let encoding = self.encoding;
+    let Some(document) = self.documents.get_mut(&uri) else {
+      return Ok(Value::Null);
+    };
+    let internal = |e: String| RpcError::new(INTERNAL_ERROR, e);
+    for change in params.content_changes {
+      let Some(range) = change.range else {
+        let lang = *document.doc.get_lang();
+        document.doc = WasmDoc::try_new(change.text, lang).map_err(|e| internal(e.to_string()))?;
+        continue;
+      };
+      let index = LineIndex::new(document.doc.chars(), encoding);
+      let start = index.offset_of(&range.start);
+      let end = index.offset_of(&range.end).max(start);
+      let edit = Edit::<Wrapper> {
+        position: start,
+        deleted_length: end - start,
+        inserted_text: change.text.chars().collect(),
+      };
+      document.doc.do_edit(&edit).map_err(internal)?;
+    }
+    document.version = params.text_document.version;
+    self.rescan(&uri)?;
+    out.push(self.publish(&uri));
+    Ok(Value::Null)
+  }
+
+  fn rescan(&mut self, uri: &str) -> Result<(), RpcError> {
+    let internal = |e: String| RpcError::new(INTERNAL_ERROR, e);
+    let Some(document) = self.documents.get_mut(uri) else {
+      return Ok(());
+    };
+    document.findings.clear();
+    let root = AstGrep::doc(document.doc.clone());
+    let mut matches = root_matches(&root, &self.rules, &Default::default()).map_err(internal)?;
+    let edits = root_edits(&root, &self.rules).map_err(internal)?;
+    let index = LineIndex::new(document.doc.chars(), self.encoding);
+    for (i, rule) in self.rules.iter().enumerate() {
+      if matches!(rule.severity, Severity::Off) {
+        continue;
+      }
+      for node in matches.remove(&rule.id).unwrap_or_default() {
+        let (line, column, _, _) = node.node.range;
+        let start = index.offset(line, column);
+        let fix = edits
+          .iter()
+          .find(|(id, edit)| *id == rule.id && edit.position == start)
+          .map(|(_, edit)| (edit.position, edit.deleted_length, edit.inserted_text.iter().collect()));
+        document.findings.push(Finding { rule: i, node, fix });
+      }
+    }
+    document.findings.sort_by_key(|f| (f.node.node.range.0, f.node.node.range.1));
+    Ok(())
+  }
+
+  fn publish(&self, uri: &str) -> Value {
+    let document = &self.documents[uri];
+    let index = LineIndex::new(document.doc.chars(), self.encoding);
+    let diagnostics: Vec<_> = document.findings.iter().map(|f| self.diagnostic(f, &index)).collect();
+    let params = json!({ "uri": uri, "version": document.version, "diagnostics": diagnostics });
+    notification("textDocument/publishDiagnostics", params)
+  }
+
+  fn diagnostic(&self, finding: &Finding, index: &LineIndex) -> Value {
+    let rule = &self.rules[finding.rule];
+    let severity = match rule.severity {
+      Severity::Error => 1,
+      Severity::Warning => 2,
+      Severity::Info => 3,
+      Severity::Hint | Severity::Off => 4,
+    };
+    let message = if finding.node.message.is_empty() {
+      &rule.id
+    } else {
+      &finding.node.message
+    };
+    let mut diagnostic = json!({
+      "range": index.range(finding.node.node.range),
+      "severity": severity,
+      "code": rule.id,
+      "source": "ast-grep",
+      "message": message,
+    });
+    if let Some(url) = &rule.url {
+      diagnostic["codeDescription"] = json!({ "href": url });
+    }
+    diagnostic
+  }
+
+  fn code_action(&self, params: CodeActionParams) -> Value {
+    let uri = params.text_document.uri;
+    let Some(document) = self.documents.get(&uri) else {
+      return json!([]);
+    };
+    let index = LineIndex::new(document.doc.chars(), self.encoding);
+    let (start, end) = (index.offset_of(&params.range.start), index.offset_of(&params.range.end));
+    let mut actions = vec![];
+    for finding in &document.findings {
+      let Some((position, deleted_length, text)) = &finding.fix else {
+        continue;
+      };
+      let (line, column, end_line, end_column) = finding.node.node.range;
+      if index.offset(line, column) > end || index.offset(end_line, end_column) < start {
+        continue;
+      }
+      let fix_start = pos_of(&index, *position);
+      let fix_end = pos_of(&index, position + deleted_length);
+      let range = index.range((fix_start.0, fix_start.1, fix_end.0, fix_end.1));
+      let rule = &self.rules[finding.rule];
+      actions.push(json!({
+        "title": format!("Fix `{}` with ast-grep", rule.id),
+        "kind": "quickfix",
+        "diagnostics": [self.diagnostic(finding, &index)],
+        "isPreferred": true,
+        "edit": { "changes": { uri.as_str(): [{ "range": range, "newText": text }] } },
+      }));
+    }
+    json!(actions)
+  }
+
+  fn hover(&self, params: HoverParams) -> Value {
+    let Some(document) = self.documents.get(&params.text_document.uri) else {
+      return Value::Null;
+    };
+    let index = LineIndex::new(document.doc.chars(), self.encoding);
+    let offset = index.offset_of(&params.position);
+    let found: Vec<_> = document
+      .findings
+      .iter()
+      .filter(|f| {
+        let (line, column, end_line, end_column) = f.node.node.range;
+        index.offset(line, column) <= offset && offset <= index.offset(end_line, end_column)
+      })
+      .collect();
+    // the innermost match decides the hover range
+    let Some(innermost) = found.iter().min_by_key(|f| f.node.node.text.chars().count()) else {
+      return Value::Null;
+    };
+    let sections: Vec<_> = found
+      .iter()
+      .map(|f| {
+        let rule = &self.rules[f.rule];
+        let mut section = format!("**{}**", rule.id);
+        if !f.node.message.is_empty() {
+          section.push_str(&format!(": {}", f.node.message));
+        }
+        if let Some(note) = &rule.note {
+          section.push_str(&format!("\n\n{}", note));
+        }
+        section
+      })
+      .collect();
+    json!({
+      "contents": { "kind": "markdown", "value": sections.join("\n\n---\n\n") },
+      "range": index.range(innermost.node.node.range),
+    })
+  }
+}
+
+/// editors name a few languages differently
+fn language_of(language_id: &str) -> Option<WasmLang> {
+  let lang = match language_id {
+    "javascriptreact" => "javascript",
+    "typescriptreact" => "tsx",
+    "shellscript" => "bash",
+    id => id,
+  };
+  WasmLang::from_str(lang).ok()
+}
+
+/// line and UTF-16 column of a char offset
+fn pos_of(index: &LineIndex, offset: usize) -> (usize, usize) {
+  let line = index.starts.partition_point(|&s| s <= offset).saturating_sub(1);
+  let start = index.starts[line];
+  let column = index.chars[start..offset.min(index.chars.len())].iter().map(|c| c.len_utf16()).sum();
+  (line, column)
+}
+
+fn parse_params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+  serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+fn notification(method: &str, params: Value) -> Value {
+  json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+/// `window/logMessage`, 1 for errors and 2 for warnings
+fn log_message(kind: u8, message: &str) -> Value {
+  notification("window/logMessage", json!({ "type": kind, "message": message }))
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+  json!({
+    "jsonrpc": "2.0",
+    "id": id,
+    "error": { "code": error.code, "message": error.message },
+  })
+}
+
+#[cfg(all(test, feature = "native"))]
+mod test {
+  use super::*;
+  use crate::rule_from_value;
+  use crate::test::use_langs;
+
+  const URI: &str = "file:///test.js";
+
+  fn server() -> Server {
+    let rule = rule_from_value(json!({
+      "id": "no-log",
+      "language": "javascript",
+      "severity": "warning",
+      "message": "remove log",
+      "note": "use logger",
+      "rule": { "pattern": "console.log($A)" },
+      "fix": "logger.log($A)",
+    }));
+    Server::new(vec![rule.expect("rule should be valid")])
+  }
+
+  fn send(server: &mut Server, method: &str, params: Value) -> Vec<Value> {
+    let message = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+    server.handle(&message.to_string())
+  }
+
+  fn notify(server: &mut Server, method: &str, params: Value) -> Value {
+    let message = json!({ "jsonrpc": "2.0", "method": method, "params": params });
+    let mut out = server.handle(&message.to_string());
+    assert_eq!(out.len(), 1);
+    out.remove(0)
+  }
+
+  fn open(server: &mut Server, text: &str) -> Value {
+    let item = json!({ "uri": URI, "languageId": "javascript", "version": 1, "text": text });
+    notify(server, "textDocument/didOpen", json!({ "textDocument": item }))
+  }
+
+  #[test]
+  fn test_publish_diagnostics() {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let mut server = server();
+    let init = send(&mut server, "initialize", json!({ "capabilities": {} }));
+    assert_eq!(init[0]["result"]["capabilities"]["positionEncoding"], "utf-16");
+    let published = open(&mut server, "console.log('😀')\nfoo()");
+    let diagnostics = &published["params"]["diagnostics"];
+    assert_eq!(diagnostics.as_array().map(Vec::len), Some(1));
+    assert_eq!(diagnostics[0]["range"]["end"], json!({ "line": 0, "character": 17 }));
+    assert_eq!(diagnostics[0]["severity"], 2);
+    assert_eq!(diagnostics[0]["message"], "remove log");
+    let change = json!({
+      "range": { "start": { "line": 1, "character": 0 }, "end": { "line": 1, "character": 5 } },
+      "text": "console.log(b)",
+    });
+    let params = json!({ "textDocument": { "uri": URI, "version": 2 }, "contentChanges": [change] });
+    let published = notify(&mut server, "textDocument/didChange", params);
+    assert_eq!(published["params"]["version"], 2);
+    let diagnostics = &published["params"]["diagnostics"];
+    assert_eq!(diagnostics[1]["range"]["end"], json!({ "line": 1, "character": 14 }));
+    // fixes after the emoji are still found
+    let range = json!({ "start": { "line": 1, "character": 0 }, "end": { "line": 1, "character": 0 } });
+    let params = json!({ "textDocument": { "uri": URI }, "range": range, "context": { "diagnostics": [] } });
+    let actions = &send(&mut server, "textDocument/codeAction", params)[0]["result"];
+    assert_eq!(actions.as_array().map(Vec::len), Some(1));
+    let edit = &actions[0]["edit"]["changes"][URI][0];
+    assert_eq!(edit["newText"], "logger.log(b)");
+    assert_eq!(edit["range"]["end"], json!({ "line": 1, "character": 14 }));
+    let closed = notify(&mut server, "textDocument/didClose", json!({ "textDocument": { "uri": URI } }));
+    assert_eq!(closed["params"]["diagnostics"], json!([]));
+  }
+
+  #[test]
+  fn test_code_action_and_hover() {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let mut server = server();
+    open(&mut server, "foo()\nconsole.log(a)");
+    let range = json!({ "start": { "line": 1, "character": 2 }, "end": { "line": 1, "character": 2 } });
+    let params = json!({ "textDocument": { "uri": URI }, "range": range, "context": { "diagnostics": [] } });
+    let actions = &send(&mut server, "textDocument/codeAction", params)[0]["result"];
+    let edit = &actions[0]["edit"]["changes"][URI][0];
+    assert_eq!(edit["newText"], "logger.log(a)");
+    assert_eq!(edit["range"]["start"], json!({ "line": 1, "character": 0 }));
+    let position = json!({ "line": 1, "character": 4 });
+    let params = json!({ "textDocument": { "uri": URI }, "position": position });
+    let hover = &send(&mut server, "textDocument/hover", params)[0]["result"];
+    assert_eq!(hover["contents"]["value"], "**no-log**: remove log\n\nuse logger");
+    let position = json!({ "line": 0, "character": 1 });
+    let params = json!({ "textDocument": { "uri": URI }, "position": position });
+    assert_eq!(send(&mut server, "textDocument/hover", params)[0]["result"], Value::Null);
+  }
+
+  #[test]
+  fn test_errors() {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let mut server = server();
+    assert_eq!(server.handle("{")[0]["error"]["code"], PARSE_ERROR);
+    assert_eq!(send(&mut server, "workspace/symbol", json!({}))[0]["error"]["code"], METHOD_NOT_FOUND);
+    send(&mut server, "shutdown", Value::Null);
+    assert_eq!(send(&mut server, "textDocument/hover", json!({}))[0]["error"]["code"], INVALID_REQUEST);
+  }
+}
@@ -0,0 +1,392 @@
+//! Native tree-sitter backend mirroring the parts of `web_tree_sitter_sg` used by this crate,
+//! so the matching logic can run under plain `cargo test --features native`.
+//! Sources are parsed as UTF-16 and offsets are reported in code units like web-tree-sitter.
+
+use crate::wasm_lang::WasmLang;
+use std::cell::RefCell;
+use std::fmt;
+use std::sync::Arc;
+use tree_sitter as native;
+use wasm_bindgen::JsError;
+
+pub struct TreeSitter;
+
+impl TreeSitter {
+  pub async fn init() -> Result<(), JsError> {
+    Ok(())
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct ParserError(String);
+
+impl ParserError {
+  pub fn message(&self) -> String {
+    self.0.clone()
+  }
+}
+
+impl fmt::Display for ParserError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(&self.0)
+  }
+}
+
+impl std::error::Error for ParserError {}
+
+#[derive(Clone, Debug)]
+pub struct LanguageError(String);
+
+impl LanguageError {
+  pub fn message(&self) -> String {
+    self.0.clone()
+  }
+}
+
+impl fmt::Display for LanguageError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(&self.0)
+  }
+}
+
+impl std::error::Error for LanguageError {}
+
+/// Stands in for `js_sys::JsString`, so call sites convert kinds and text
+/// the same way for both backends.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JsString(String);
+
+impl fmt::Display for JsString {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(&self.0)
+  }
+}
+
+impl From<String> for JsString {
+  fn from(s: String) -> Self {
+    Self(s)
+  }
+}
+
+impl From<&str> for JsString {
+  fn from(s: &str) -> Self {
+    Self(s.to_string())
+  }
+}
+
+impl From<JsString> for String {
+  fn from(s: JsString) -> Self {
+    s.0
+  }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Point {
+  row: u32,
+  column: u32,
+}
+
+impl Point {
+  pub fn new(row: u32, column: u32) -> Self {
+    Self { row, column }
+  }
+  pub fn row(&self) -> u32 {
+    self.row
+  }
+  pub fn column(&self) -> u32 {
+    self.column
+  }
+}
+
+// native columns and offsets count bytes of the UTF-16 input
+impl From<native::Point> for Point {
+  fn from(p: native::Point) -> Self {
+    Self::new(p.row as u32, (p.column / 2) as u32)
+  }
+}
+
+impl From<&Point> for native::Point {
+  fn from(p: &Point) -> Self {
+    native::Point::new(p.row as usize, p.column as usize * 2)
+  }
+}
+
+#[derive(Clone)]
+pub struct Language(native::Language);
+
+impl Language {
+  pub fn for_lang(lang: WasmLang) -> Result<Self, LanguageError> {
+    let ts_lang: native::Language = match lang {
+      WasmLang::JavaScript => tree_sitter_javascript::LANGUAGE.into(),
+      WasmLang::TypeScript => tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
+      WasmLang::Tsx => tree_sitter_typescript::LANGUAGE_TSX.into(),
+      WasmLang::Css => tree_sitter_css::LANGUAGE.into(),
+      WasmLang::Html => tree_sitter_html::LANGUAGE.into(),
+      WasmLang::Json => tree_sitter_json::LANGUAGE.into(),
+      WasmLang::Python => tree_sitter_python::LANGUAGE.into(),
+      WasmLang::Rust => tree_sitter_rust::LANGUAGE.into(),
+      _ => return Err(LanguageError(format!("no native grammar for {}", lang))),
+    };
+    Ok(Self(ts_lang))
+  }
+
+  pub fn id_for_node_kind(&self, kind: &str, named: bool) -> u16 {
+    self.0.id_for_node_kind(kind, named)
+  }
+  pub fn field_id_for_name(&self, name: &str) -> Option<u16> {
+    self.0.field_id_for_name(name).map(u16::from)
+  }
+  pub fn node_kind_count(&self) -> u16 {
+    self.0.node_kind_count() as u16
+  }
+  pub fn node_kind_for_id(&self, id: u16) -> Option<String> {
+    self.0.node_kind_for_id(id).map(String::from)
+  }
+  pub fn node_kind_is_named(&self, id: u16) -> bool {
+    self.0.node_kind_is_named(id)
+  }
+  pub fn node_kind_is_visible(&self, id: u16) -> bool {
+    self.0.node_kind_is_visible(id)
+  }
+  pub fn field_count(&self) -> u16 {
+    self.0.field_count() as u16
+  }
+  pub fn field_name_for_id(&self, id: u16) -> Option<String> {
+    self.0.field_name_for_id(id).map(String::from)
+  }
+  pub fn supertypes(&self) -> Vec<u16> {
+    self.0.supertypes().to_vec()
+  }
+}
+
+pub struct Parser(RefCell<native::Parser>);
+
+impl Parser {
+  pub fn new() -> Result<Self, ParserError> {
+    Ok(Self(RefCell::new(native::Parser::new())))
+  }
+
+  pub fn set_language(&self, lang: Option<&Language>) -> Result<(), LanguageError> {
+    let Some(lang) = lang else {
+      self.0.borrow_mut().reset();
+      return Ok(());
+    };
+    self.0.borrow_mut().set_language(&lang.0).map_err(|e| LanguageError(e.to_string()))
+  }
+
+  pub fn parse_with_string(
+    &self,
+    src: &JsString,
+    old_tree: Option<&Tree>,
+    _options: Option<&()>,
+  ) -> Result<Option<Tree>, ParserError> {
+    let source: Vec<u16> = src.0.encode_utf16().collect();
+    let old_tree = old_tree.map(|t| &t.0.tree);
+    let tree = self.0.borrow_mut().parse_utf16_le(&source, old_tree);
+    Ok(tree.map(|tree| Tree(Arc::new(TreeInner { tree, source }))))
+  }
+}
+
+pub struct Edit(native::InputEdit);
+
+impl Edit {
+  pub fn new(
+    start_index: u32,
+    old_end_index: u32,
+    new_end_index: u32,
+    start_position: &Point,
+    old_end_position: &Point,
+    new_end_position: &Point,
+  ) -> Self {
+    Self(native::InputEdit {
+      start_byte: start_index as usize * 2,
+      old_end_byte: old_end_index as usize * 2,
+      new_end_byte: new_end_index as usize * 2,
+      start_position: start_position.into(),
+      old_end_position: old_end_position.into(),
+      new_end_position: new_end_position.into(),
+    })
+  }
+}
+
+#[derive(Clone)]
+struct TreeInner {
+  tree: native::Tree,
+  source: Vec<u16>,
+}
+
+#[derive(Clone)]
+pub struct Tree(Arc<TreeInner>);
+
+impl Tree {
+  pub fn root_node(&self) -> SyntaxNode {
+    SyntaxNode::new(self.0.clone(), self.0.tree.root_node())
+  }
+
+  /// nodes and cursors share the Arc, so editing a tree they point into
+  /// clones it first and they keep reading the unedited copy
+  pub fn edit(&mut self, edit: &Edit) {
+    Arc::make_mut(&mut self.0).tree.edit(&edit.0);
+  }
+}
+
+/// A node that keeps its tree alive, like a js object would.
+/// `native::Node` borrows the `native::Tree`, which cannot be expressed for a struct
+/// owning both, so the borrow is erased to `'static` and the Arc stands in for it.
+#[derive(Clone)]
+pub struct SyntaxNode {
+  // declared before `tree` so it is dropped first
+  node: native::Node<'static>,
+  tree: Arc<TreeInner>,
+}
+
+impl SyntaxNode {
+  fn new(tree: Arc<TreeInner>, node: native::Node<'_>) -> Self {
+    // SAFETY: `node` belongs to `tree.tree`, whose heap allocation does not move when
+    // the Arc is cloned and is never mutated while shared, see `Tree::edit`.
+    // The node never outlives the Arc it is stored with, and it is only handed out
+    // with lifetimes bound to `self`.
+    let node = unsafe { std::mem::transmute::<native::Node<'_>, native::Node<'static>>(node) };
+    Self { node, tree }
+  }
+
+  fn wrap(&self, node: native::Node<'_>) -> Self {
+    Self::new(self.tree.clone(), node)
+  }
+
+  // web-tree-sitter ids are addresses in the 32-bit wasm heap
+  pub fn id(&self) -> u32 {
+    self.node.id() as u32
+  }
+  pub fn type_(&self) -> JsString {
+    self.node.kind().into()
+  }
+  pub fn type_id(&self) -> u16 {
+    self.node.kind_id()
+  }
+  pub fn text(&self) -> JsString {
+    let range = self.start_index() as usize..self.end_index() as usize;
+    String::from_utf16_lossy(&self.tree.source[range]).into()
+  }
+  pub fn start_index(&self) -> u32 {
+    (self.node.start_byte() / 2) as u32
+  }
+  pub fn end_index(&self) -> u32 {
+    (self.node.end_byte() / 2) as u32
+  }
+  pub fn start_position(&self) -> Point {
+    self.node.start_position().into()
+  }
+  pub fn end_position(&self) -> Point {
+    self.node.end_position().into()
+  }
+  pub fn is_named(&self) -> bool {
+    self.node.is_named()
+  }
+  pub fn is_missing(&self) -> bool {
+    self.node.is_missing()
+  }
+  pub fn is_error(&self) -> bool {
+    self.node.is_error()
+  }
+  pub fn is_extra(&self) -> bool {
+    self.node.is_extra()
+  }
+  pub fn has_error(&self) -> bool {
+    self.node.has_error()
+  }
+  pub fn parent(&self) -> Option<Self> {
+    self.node.parent().map(|n| self.wrap(n))
+  }
+  pub fn child(&self, index: u32) -> Option<Self> {
+    self.node.child(index as usize).map(|n| self.wrap(n))
+  }
+  pub fn children(&self) -> Vec<Self> {
+    let mut cursor = self.node.walk();
+    self.node.children(&mut cursor).map(|n| self.wrap(n)).collect()
+  }
+  pub fn child_count(&self) -> u32 {
+    self.node.child_count() as u32
+  }
+  pub fn named_child_count(&self) -> u32 {
+    self.node.named_child_count() as u32
+  }
+  pub fn next_sibling(&self) -> Option<Self> {
+    self.node.next_sibling().map(|n| self.wrap(n))
+  }
+  pub fn previous_sibling(&self) -> Option<Self> {
+    self.node.prev_sibling().map(|n| self.wrap(n))
+  }
+  pub fn child_for_field_id(&self, field_id: u16) -> Option<Self> {
+    self.node.child_by_field_id(field_id).map(|n| self.wrap(n))
+  }
+  pub fn child_for_field_name(&self, name: &str) -> Option<Self> {
+    self.node.child_by_field_name(name).map(|n| self.wrap(n))
+  }
+  pub fn descendant_for_index(&self, index: u32) -> Option<Self> {
+    self.descendant_for_index_range(index, index)
+  }
+  pub fn descendant_for_index_range(&self, start: u32, end: u32) -> Option<Self> {
+    let (start, end) = (start as usize * 2, end as usize * 2);
+    self.node.descendant_for_byte_range(start, end).map(|n| self.wrap(n))
+  }
+  pub fn named_descendant_for_index(&self, index: u32) -> Option<Self> {
+    self.named_descendant_for_index_range(index, index)
+  }
+  pub fn named_descendant_for_index_range(&self, start: u32, end: u32) -> Option<Self> {
+    let (start, end) = (start as usize * 2, end as usize * 2);
+    self.node.named_descendant_for_byte_range(start, end).map(|n| self.wrap(n))
+  }
+  pub fn descendant_for_position(&self, position: &Point) -> Option<Self> {
+    self.descendant_for_position_range(position, position)
+  }
+  pub fn descendant_for_position_range(&self, start: &Point, end: &Point) -> Option<Self> {
+    let node = self.node.descendant_for_point_range(start.into(), end.into());
+    node.map(|n| self.wrap(n))
+  }
+  pub fn named_descendant_for_position(&self, position: &Point) -> Option<Self> {
+    self.named_descendant_for_position_range(position, position)
+  }
+  pub fn named_descendant_for_position_range(&self, start: &Point, end: &Point) -> Option<Self> {
+    let node = self.node.named_descendant_for_point_range(start.into(), end.into());
+    node.map(|n| self.wrap(n))
+  }
+  pub fn walk(&self) -> TreeCursor {
+    let cursor = self.node.walk();
+    TreeCursor {
+      // SAFETY: same as `SyntaxNode::new`, the cursor is dropped before the Arc
+      // keeping its tree alive
+      cursor: RefCell::new(unsafe {
+        std::mem::transmute::<native::TreeCursor<'_>, native::TreeCursor<'static>>(cursor)
+      }),
+      tree: self.tree.clone(),
+    }
+  }
+}
+
+pub struct TreeCursor {
+  // declared before `tree` so it is dropped first
+  cursor: RefCell<native::TreeCursor<'static>>,
+  tree: Arc<TreeInner>,
+}
+
+impl TreeCursor {
+  pub fn current_node(&self) -> SyntaxNode {
+    SyntaxNode::new(self.tree.clone(), self.cursor.borrow().node())
+  }
+  pub fn current_field_name(&self) -> Option<JsString> {
+    self.cursor.borrow().field_name().map(JsString::from)
+  }
+  pub fn current_field_id(&self) -> Option<u16> {
+    self.cursor.borrow().field_id().map(u16::from)
+  }
+  pub fn goto_first_child(&self) -> bool {
+    self.cursor.borrow_mut().goto_first_child()
+  }
+  pub fn goto_next_sibling(&self) -> bool {
+    self.cursor.borrow_mut().goto_next_sibling()
+  }
+  pub fn goto_parent(&self) -> bool {
+    self.cursor.borrow_mut().goto_parent()
+  }
+}
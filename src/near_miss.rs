@@ -0,0 +1,281 @@
+use crate::error_message;
+use crate::injection::{extract_injections, Injection};
+use crate::utils::{WasmMatch, WasmNode};
+use crate::wasm_lang::{WasmDoc, WasmLang};
+use ast_grep_config::{RuleConfig, RuleCore, SerializableRuleConfig};
+use ast_grep_core::matcher::MatcherExt;
+use ast_grep_core::AstGrep;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RejectedBy {
+  Constraint,
+  Not,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Rejection {
+  pub rejected_by: RejectedBy,
+  /// the constrained metavariable without `$`, None for `not`
+  pub metavar: Option<String>,
+  /// the failing constraint or the matching `not` rule, e.g. `{ "regex": "^foo" }`
+  pub rule: Value,
+  /// the node checked by the rule
+  pub captured: WasmNode,
+}
+
+/// a node matched by the rule without its constraints and `not` clauses
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NearMiss {
+  #[serde(flatten)]
+  pub matched: WasmMatch,
+  pub rejections: Vec<Rejection>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DebugMatches {
+  pub matches: HashMap<String, Vec<WasmMatch>>,
+  pub near_misses: HashMap<String, Vec<NearMiss>>,
+}
+
+struct Check {
+  metavar: Option<String>,
+  rule: Value,
+  matcher: RuleCore,
+}
+
+/// replays a rule without `constraints` and top level `not` to explain rejected nodes
+pub struct Explainer<'r> {
+  rule: &'r RuleConfig<WasmLang>,
+  relaxed: RuleCore,
+  constraints: Vec<Check>,
+  nots: Vec<Check>,
+}
+
+impl<'r> Explainer<'r> {
+  /// None if the rule has nothing to relax, an error if the relaxed rule or a check does not compile
+  pub fn new(rule: &'r RuleConfig<WasmLang>) -> Result<Option<Self>, String> {
+    let mut config = serde_json::to_value(&**rule).map_err(error_message)?;
+    let utils = config["utils"].clone();
+    let checker = |id: String, metavar: Option<String>, checked: Value| {
+      let matcher = compile(json!({
+        "id": id,
+        "language": rule.language.to_string(),
+        "rule": checked,
+        "utils": &utils,
+      }))
+      .map_err(|e| format!("`{}` of rule `{}`: {}", id, rule.id, e))?;
+      Ok::<_, String>(Check {
+        metavar,
+        rule: checked,
+        matcher,
+      })
+    };
+    let mut constraints = vec![];
+    if let Some(map) = config["constraints"].as_object() {
+      let mut vars: Vec<_> = map.iter().collect();
+      vars.sort_by_key(|(var, _)| var.as_str());
+      for (var, checked) in vars {
+        let id = format!("{}/constraints/{}", rule.id, var);
+        constraints.push(checker(id, Some(var.clone()), checked.clone())?);
+      }
+    }
+    let mut nots = vec![];
+    for checked in take_nots(&mut config["rule"]) {
+      let id = format!("{}/not/{}", rule.id, nots.len());
+      nots.push(checker(id, None, checked)?);
+    }
+    if constraints.is_empty() && nots.is_empty() {
+      return Ok(None);
+    }
+    config["constraints"] = Value::Null;
+    let relaxed = compile(config).map_err(|e| format!("rule `{}` without constraints and `not`: {}", rule.id, e))?;
+    Ok(Some(Self {
+      rule,
+      relaxed,
+      constraints,
+      nots,
+    }))
+  }
+
+  pub fn near_misses(&self, root: &AstGrep<WasmDoc>) -> Vec<NearMiss> {
+    let mut ret = vec![];
+    for node in root.root().dfs() {
+      let Some(nm) = self.relaxed.match_node(node.clone()) else {
+        continue;
+      };
+      if self.rule.matcher.match_node(node.clone()).is_some() {
+        continue;
+      }
+      let mut rejections = vec![];
+      for check in &self.constraints {
+        let var = check.metavar.as_deref().unwrap_or_default();
+        // like ast-grep, a constraint on a missing metavariable passes
+        let Some(captured) = nm.get_env().get_match(var) else {
+          continue;
+        };
+        if check.matcher.match_node(captured.clone()).is_none() {
+          rejections.push(check.rejection(RejectedBy::Constraint, WasmNode::from(captured.clone())));
+        }
+      }
+      for check in &self.nots {
+        if check.matcher.match_node(node.clone()).is_some() {
+          rejections.push(check.rejection(RejectedBy::Not, WasmNode::from(node.clone())));
+        }
+      }
+      // `not` rules using metavariables of the outer rule cannot be replayed alone
+      if rejections.is_empty() {
+        continue;
+      }
+      ret.push(NearMiss {
+        matched: WasmMatch::from_match(nm, self.rule),
+        rejections,
+      });
+    }
+    ret
+  }
+}
+
+impl Check {
+  fn rejection(&self, rejected_by: RejectedBy, captured: WasmNode) -> Rejection {
+    Rejection {
+      rejected_by,
+      metavar: self.metavar.clone(),
+      rule: self.rule.clone(),
+      captured,
+    }
+  }
+}
+
+/// checks only match captured nodes, so unlike a rule they need no kinds
+fn compile(value: Value) -> Result<RuleCore, String> {
+  let config: SerializableRuleConfig<WasmLang> = serde_json::from_value(value).map_err(error_message)?;
+  config.get_matcher(&Default::default()).map_err(error_message)
+}
+
+/// remove `not` from the rule and from the items of its `all`
+fn take_nots(rule: &mut Value) -> Vec<Value> {
+  let mut ret = vec![];
+  let Some(obj) = rule.as_object_mut() else {
+    return ret;
+  };
+  ret.extend(obj.remove("not"));
+  if let Some(Value::Array(all)) = obj.get_mut("all") {
+    for item in all.iter_mut() {
+      if let Some(item) = item.as_object_mut() {
+        ret.extend(item.remove("not"));
+      }
+    }
+    all.retain(|item| item.as_object().map_or(true, |o| !o.is_empty()));
+  }
+  ret.retain(|r| !r.is_null());
+  ret
+}
+
+/// near-misses of the host and injected documents
+pub fn root_near_misses(
+  root: &AstGrep<WasmDoc>,
+  rules: &[RuleConfig<WasmLang>],
+) -> Result<HashMap<String, Vec<NearMiss>>, String> {
+  let mut ret = HashMap::new();
+  collect_near_misses(root, rules, None, &mut ret)?;
+  for injection in extract_injections(root).map_err(error_message)? {
+    collect_near_misses(&injection.root, rules, Some(&injection), &mut ret)?;
+  }
+  Ok(ret)
+}
+
+fn collect_near_misses(
+  root: &AstGrep<WasmDoc>,
+  rules: &[RuleConfig<WasmLang>],
+  injection: Option<&Injection>,
+  ret: &mut HashMap<String, Vec<NearMiss>>,
+) -> Result<(), String> {
+  let lang = *root.root().lang();
+  for rule in rules.iter().filter(|r| r.language == lang) {
+    let Some(explainer) = Explainer::new(rule)? else {
+      continue;
+    };
+    let mut found = explainer.near_misses(root);
+    if found.is_empty() {
+      continue;
+    }
+    if let Some(injection) = injection {
+      for near_miss in &mut found {
+        injection.map_match(&mut near_miss.matched);
+        for rejection in &mut near_miss.rejections {
+          injection.map_node(&mut rejection.captured);
+        }
+      }
+    }
+    ret.entry(rule.id.clone()).or_default().extend(found);
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_take_nots() {
+    let mut rule = json!({
+      "pattern": "$A",
+      "not": { "kind": "number" },
+      "all": [{ "not": { "regex": "a" } }, { "kind": "call_expression", "not": { "regex": "b" } }],
+    });
+    let nots = take_nots(&mut rule);
+    assert_eq!(nots, [json!({ "kind": "number" }), json!({ "regex": "a" }), json!({ "regex": "b" })]);
+    assert_eq!(rule, json!({ "pattern": "$A", "all": [{ "kind": "call_expression" }] }));
+  }
+}
+
+#[cfg(all(test, feature = "native"))]
+mod native_test {
+  use super::*;
+  use crate::rule_from_value;
+  use crate::test::use_langs;
+
+  #[test]
+  fn test_near_misses() {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let rule = rule_from_value(json!({
+      "id": "log-f",
+      "language": "javascript",
+      "rule": { "pattern": "console.log($A)", "not": { "pattern": "console.log(skip)" } },
+      "constraints": { "A": { "regex": "^f" } },
+    }));
+    let rules = vec![rule.expect("rule should be valid")];
+    let src = "console.log(foo)\nconsole.log(bar)\nconsole.log(skip)";
+    let root = AstGrep::doc(WasmDoc::try_new(src.into(), WasmLang::JavaScript).expect("should parse"));
+    let found = root_near_misses(&root, &rules).expect("should scan");
+    let found = &found["log-f"];
+    assert_eq!(found.len(), 2);
+    assert_eq!(found[0].matched.node.text, "console.log(bar)");
+    let rejection = &found[0].rejections[0];
+    assert_eq!(rejection.metavar.as_deref(), Some("A"));
+    assert_eq!(rejection.rule, json!({ "regex": "^f" }));
+    assert_eq!(rejection.captured.text, "bar");
+    let kinds: Vec<_> = found[1].rejections.iter().map(|r| r.metavar.is_some()).collect();
+    assert_eq!(kinds, [true, false]);
+    assert_eq!(found[1].rejections[1].captured.text, "console.log(skip)");
+  }
+
+  #[test]
+  fn test_nothing_to_relax() {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let rule = rule_from_value(json!({
+      "id": "log",
+      "language": "javascript",
+      "rule": { "pattern": "console.log($A)" },
+    }));
+    let rule = rule.expect("rule should be valid");
+    assert!(matches!(Explainer::new(&rule), Ok(None)));
+  }
+}
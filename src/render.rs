@@ -0,0 +1,408 @@
+use serde::{Deserialize, Serialize};
+use crate::ts::{SyntaxNode, TreeCursor};
+
+#[derive(Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TextFormat {
+  /// tree-sitter style s-expression with field names
+  #[default]
+  Sexp,
+  /// indented outline like `--debug-query=ast`
+  Outline,
+  /// single line json with abbreviated keys
+  Json,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RenderOptions {
+  pub format: TextFormat,
+  pub named_only: bool,
+  pub leaf_text: bool,
+}
+
+pub fn render_tree(cursor: &mut TreeCursor, options: &RenderOptions) -> String {
+  match options.format {
+    TextFormat::Sexp => sexp(cursor, options).unwrap_or_default(),
+    TextFormat::Outline => {
+      let mut out = String::new();
+      outline(cursor, options, 0, &mut out);
+      out
+    }
+    TextFormat::Json => {
+      let node = compact(cursor, options);
+      serde_json::to_string(&node).unwrap_or_default()
+    }
+  }
+}
+
+pub fn node_kind(node: &SyntaxNode) -> String {
+  if node.is_missing() {
+    format!("MISSING {}", node.type_())
+  } else {
+    format!("{}", node.type_())
+  }
+}
+
+fn is_leaf(node: &SyntaxNode, options: &RenderOptions) -> bool {
+  if options.named_only {
+    node.named_child_count() == 0
+  } else {
+    node.child_count() == 0
+  }
+}
+
+fn leaf_text(node: &SyntaxNode, options: &RenderOptions) -> Option<String> {
+  if options.leaf_text && is_leaf(node, options) {
+    Some(node.text().into())
+  } else {
+    None
+  }
+}
+
+fn for_each_child(cursor: &mut TreeCursor, mut f: impl FnMut(&mut TreeCursor)) {
+  if !cursor.goto_first_child() {
+    return;
+  }
+  loop {
+    f(cursor);
+    if !cursor.goto_next_sibling() {
+      break;
+    }
+  }
+  cursor.goto_parent();
+}
+
+fn sexp(cursor: &mut TreeCursor, options: &RenderOptions) -> Option<String> {
+  let node = cursor.current_node();
+  if options.named_only && !node.is_named() {
+    return None;
+  }
+  let mut ret = cursor
+    .current_field_name()
+    .map(|f| format!("{}: ", f))
+    .unwrap_or_default();
+  if !node.is_named() {
+    ret += &format!("{:?}", node_kind(&node));
+    return Some(ret);
+  }
+  ret += &format!("({}", node_kind(&node));
+  if let Some(text) = leaf_text(&node, options) {
+    ret += &format!(" {:?}", text);
+  }
+  for_each_child(cursor, |c| {
+    if let Some(child) = sexp(c, options) {
+      ret.push(' ');
+      ret += &child;
+    }
+  });
+  ret.push(')');
+  Some(ret)
+}
+
+fn outline(cursor: &mut TreeCursor, options: &RenderOptions, indent: usize, out: &mut String) {
+  let node = cursor.current_node();
+  if options.named_only && !node.is_named() {
+    return;
+  }
+  out.push_str(&" ".repeat(indent));
+  if let Some(field) = cursor.current_field_name() {
+    out.push_str(&format!("{}: ", field));
+  }
+  out.push_str(&node_kind(&node));
+  if let Some(text) = leaf_text(&node, options) {
+    out.push_str(&format!(" {:?}", text));
+  }
+  let (start, end) = (node.start_position(), node.end_position());
+  out.push_str(&format!(
+    " ({},{})-({},{})\n",
+    start.row(),
+    start.column(),
+    end.row(),
+    end.column()
+  ));
+  for_each_child(cursor, |c| outline(c, options, indent + 2, out));
+}
+
+#[derive(Serialize)]
+struct CompactNode {
+  #[serde(rename = "k")]
+  kind: String,
+  #[serde(rename = "f", skip_serializing_if = "Option::is_none")]
+  field: Option<String>,
+  #[serde(rename = "r")]
+  range: [u32; 4],
+  #[serde(rename = "t", skip_serializing_if = "Option::is_none")]
+  text: Option<String>,
+  #[serde(rename = "c", skip_serializing_if = "Vec::is_empty")]
+  children: Vec<CompactNode>,
+}
+
+fn compact(cursor: &mut TreeCursor, options: &RenderOptions) -> CompactNode {
+  let node = cursor.current_node();
+  let (start, end) = (node.start_position(), node.end_position());
+  let mut children = vec![];
+  for_each_child(cursor, |c| {
+    if !options.named_only || c.current_node().is_named() {
+      children.push(compact(c, options));
+    }
+  });
+  CompactNode {
+    kind: node_kind(&node),
+    field: cursor.current_field_name().map(|f| format!("{}", f)),
+    range: [start.row(), start.column(), end.row(), end.column()],
+    text: leaf_text(&node, options),
+    children,
+  }
+}
+
+#[derive(Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum GraphFormat {
+  #[default]
+  Dot,
+  Mermaid,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct GraphOptions {
+  pub format: GraphFormat,
+  pub named_only: bool,
+  pub max_depth: Option<usize>,
+  /// offset ranges of matches to highlight
+  pub matched: Vec<(u32, u32)>,
+}
+
+impl GraphOptions {
+  pub fn is_matched(&self, (start, end): (u32, u32)) -> bool {
+    self.matched.iter().any(|&(s, e)| s <= start && end <= e)
+  }
+
+  pub fn is_too_deep(&self, depth: usize) -> bool {
+    self.max_depth.map_or(false, |max| depth >= max)
+  }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum NodeStyle {
+  Plain,
+  Anonymous,
+  Error,
+  Missing,
+  MetaVar,
+  Matched,
+}
+
+impl NodeStyle {
+  fn class(&self) -> Option<&'static str> {
+    match self {
+      NodeStyle::Plain => None,
+      NodeStyle::Anonymous => Some("anonymous"),
+      NodeStyle::Error => Some("error"),
+      NodeStyle::Missing => Some("missing"),
+      NodeStyle::MetaVar => Some("metavar"),
+      NodeStyle::Matched => Some("matched"),
+    }
+  }
+
+  fn dot_attrs(&self) -> &'static str {
+    match self {
+      NodeStyle::Plain => "",
+      NodeStyle::Anonymous => ", style=dashed",
+      NodeStyle::Error => ", style=filled, fillcolor=\"#ffd6d6\", color=\"#cc0000\"",
+      NodeStyle::Missing => ", style=\"filled,dashed\", fillcolor=\"#ffd6d6\", color=\"#cc0000\"",
+      NodeStyle::MetaVar => ", style=filled, fillcolor=\"#d6e4ff\"",
+      NodeStyle::Matched => ", style=filled, fillcolor=\"#fff3c4\"",
+    }
+  }
+}
+
+const MERMAID_CLASSES: &str = "  classDef anonymous stroke-dasharray:4
+  classDef error fill:#ffd6d6,stroke:#cc0000
+  classDef missing fill:#ffd6d6,stroke:#cc0000,stroke-dasharray:4
+  classDef metavar fill:#d6e4ff
+  classDef matched fill:#fff3c4
+";
+
+pub struct GraphNode {
+  pub label: String,
+  pub field: Option<String>,
+  pub style: NodeStyle,
+  pub children: Vec<GraphNode>,
+}
+
+impl GraphNode {
+  pub fn new(kind: &str, text: Option<&str>, field: Option<String>, style: NodeStyle) -> Self {
+    let label = match text {
+      Some(text) if text != kind => format!("{}\n{:?}", kind, text),
+      _ => kind.to_string(),
+    };
+    Self {
+      label,
+      field,
+      style,
+      children: vec![],
+    }
+  }
+}
+
+pub fn render_graph(root: &GraphNode, format: GraphFormat) -> String {
+  let mut out = String::new();
+  let mut count = 0;
+  match format {
+    GraphFormat::Dot => {
+      out.push_str("digraph ast {\n  node [shape=box, fontname=\"monospace\"];\n");
+      write_dot(root, &mut count, &mut out);
+      out.push_str("}\n");
+    }
+    GraphFormat::Mermaid => {
+      out.push_str("flowchart TD\n");
+      write_mermaid(root, &mut count, &mut out);
+      out.push_str(MERMAID_CLASSES);
+    }
+  }
+  out
+}
+
+fn write_dot(node: &GraphNode, count: &mut usize, out: &mut String) -> usize {
+  let id = *count;
+  *count += 1;
+  let label = node.label.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+  out.push_str(&format!("  n{} [label=\"{}\"{}];\n", id, label, node.style.dot_attrs()));
+  for child in &node.children {
+    let child_id = write_dot(child, count, out);
+    match &child.field {
+      Some(field) => out.push_str(&format!("  n{} -> n{} [label=\"{}\"];\n", id, child_id, field)),
+      None => out.push_str(&format!("  n{} -> n{};\n", id, child_id)),
+    }
+  }
+  id
+}
+
+fn write_mermaid(node: &GraphNode, count: &mut usize, out: &mut String) -> usize {
+  let id = *count;
+  *count += 1;
+  let label = node.label.replace('"', "#quot;").replace('\n', "<br/>");
+  out.push_str(&format!("  n{}[\"{}\"]\n", id, label));
+  if let Some(class) = node.style.class() {
+    out.push_str(&format!("  class n{} {}\n", id, class));
+  }
+  for child in &node.children {
+    let child_id = write_mermaid(child, count, out);
+    match &child.field {
+      Some(field) => out.push_str(&format!("  n{} -->|{}| n{}\n", id, field, child_id)),
+      None => out.push_str(&format!("  n{} --> n{}\n", id, child_id)),
+    }
+  }
+  id
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn graph() -> GraphNode {
+    let mut root = GraphNode::new("string", Some("\"a\"\nb"), None, NodeStyle::Matched);
+    let child = GraphNode::new("\"", None, Some("quote".into()), NodeStyle::Anonymous);
+    root.children.push(child);
+    root
+  }
+
+  #[test]
+  fn test_dot_escape() {
+    let dot = render_graph(&graph(), GraphFormat::Dot);
+    let lines: Vec<_> = dot.lines().skip(2).collect();
+    assert_eq!(
+      lines,
+      [
+        r##"  n0 [label="string\n\"\\\"a\\\"\\nb\"", style=filled, fillcolor="#fff3c4"];"##,
+        r#"  n1 [label="\"", style=dashed];"#,
+        r#"  n0 -> n1 [label="quote"];"#,
+        "}",
+      ]
+    );
+  }
+
+  #[test]
+  fn test_mermaid_escape() {
+    let mermaid = render_graph(&graph(), GraphFormat::Mermaid);
+    let lines: Vec<_> = mermaid.lines().skip(1).take(5).collect();
+    assert_eq!(
+      lines,
+      [
+        r##"  n0["string<br/>#quot;\#quot;a\#quot;\nb#quot;"]"##,
+        "  class n0 matched",
+        r##"  n1["#quot;"]"##,
+        "  class n1 anonymous",
+        "  n0 -->|quote| n1",
+      ]
+    );
+  }
+}
+
+#[cfg(all(test, feature = "native"))]
+mod native_test {
+  use super::*;
+  use crate::test::use_langs;
+  use crate::wasm_lang::{WasmDoc, WasmLang};
+  use ast_grep_core::AstGrep;
+  use serde_json::json;
+
+  fn render(src: &str, options: serde_json::Value) -> String {
+    let options: RenderOptions = serde_json::from_value(options).expect("options should be valid");
+    let doc = WasmDoc::try_new(src.into(), WasmLang::JavaScript).expect("should parse");
+    let root = AstGrep::doc(doc);
+    let mut cursor = root.root().get_inner_node().0.walk();
+    render_tree(&mut cursor, &options)
+  }
+
+  #[test]
+  fn test_render_sexp() {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let sexp = render("f(a)", json!({}));
+    let expected = r#"(program (expression_statement (call_expression function: (identifier) arguments: (arguments "(" (identifier) ")"))))"#;
+    assert_eq!(sexp, expected);
+    let sexp = render("f(a)", json!({ "namedOnly": true, "leafText": true }));
+    let expected = r#"(program (expression_statement (call_expression function: (identifier "f") arguments: (arguments (identifier "a")))))"#;
+    assert_eq!(sexp, expected);
+  }
+
+  #[test]
+  fn test_render_outline() {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let outline = render("f(a)", json!({ "format": "outline", "leafText": true }));
+    let expected = [
+      "program (0,0)-(0,4)",
+      "  expression_statement (0,0)-(0,4)",
+      "    call_expression (0,0)-(0,4)",
+      "      function: identifier \"f\" (0,0)-(0,1)",
+      "      arguments: arguments (0,1)-(0,4)",
+      "        ( \"(\" (0,1)-(0,2)",
+      "        identifier \"a\" (0,2)-(0,3)",
+      "        ) \")\" (0,3)-(0,4)",
+    ];
+    assert_eq!(outline.lines().collect::<Vec<_>>(), expected);
+  }
+
+  #[test]
+  fn test_render_compact_json() {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let compact = render("f(a)", json!({ "format": "json", "namedOnly": true, "leafText": true }));
+    let expected = json!({
+      "k": "program", "r": [0, 0, 0, 4], "c": [{
+        "k": "expression_statement", "r": [0, 0, 0, 4], "c": [{
+          "k": "call_expression", "r": [0, 0, 0, 4], "c": [
+            { "k": "identifier", "f": "function", "r": [0, 0, 0, 1], "t": "f" },
+            { "k": "arguments", "f": "arguments", "r": [0, 1, 0, 4], "c": [
+              { "k": "identifier", "r": [0, 2, 0, 3], "t": "a" },
+            ]},
+          ],
+        }],
+      }],
+    });
+    assert!(!compact.contains('\n'));
+    let parsed: serde_json::Value = serde_json::from_str(&compact).expect("should be json");
+    assert_eq!(parsed, expected);
+  }
+}
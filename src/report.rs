@@ -0,0 +1,493 @@
+use crate::utils::WasmMatch;
+use crate::wasm_lang::WasmLang;
+use crate::RuleEdit;
+use ast_grep_config::{LabelStyle, RuleConfig, Severity};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// same as the CLI `--report-style`
+#[derive(Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ReportStyle {
+  /// code frames with labels, notes and help
+  #[default]
+  Rich,
+  /// location and message followed by notes and help
+  Medium,
+  /// one line per match
+  Short,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ColorMode {
+  #[default]
+  None,
+  Ansi,
+  /// `<span class="sg-..">`, with the text escaped
+  Html,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ReportOptions {
+  pub style: ReportStyle,
+  pub color: ColorMode,
+  /// file name shown before line and column
+  pub path: Option<String>,
+}
+
+#[derive(Clone, Copy)]
+enum Level {
+  Error,
+  Warning,
+  Info,
+  Hint,
+}
+
+impl Level {
+  fn from_severity(severity: &Severity) -> Option<Self> {
+    match severity {
+      Severity::Error => Some(Level::Error),
+      Severity::Warning => Some(Level::Warning),
+      Severity::Info => Some(Level::Info),
+      Severity::Hint => Some(Level::Hint),
+      Severity::Off => None,
+    }
+  }
+
+  fn name(self) -> &'static str {
+    match self {
+      Level::Error => "error",
+      Level::Warning => "warning",
+      Level::Info => "info",
+      Level::Hint => "hint",
+    }
+  }
+}
+
+#[derive(Clone, Copy)]
+enum Paint {
+  Header(Level),
+  Primary(Level),
+  Secondary,
+  Gutter,
+  Bold,
+}
+
+impl Paint {
+  fn ansi(self) -> String {
+    let color = |level| match level {
+      Level::Error => "31",
+      Level::Warning => "33",
+      Level::Info => "32",
+      Level::Hint => "36",
+    };
+    match self {
+      Paint::Header(level) => format!("1;{}", color(level)),
+      Paint::Primary(level) => color(level).to_string(),
+      Paint::Secondary | Paint::Gutter => "34".to_string(),
+      Paint::Bold => "1".to_string(),
+    }
+  }
+
+  fn class(self) -> String {
+    match self {
+      Paint::Header(level) => format!("sg-header sg-{}", level.name()),
+      Paint::Primary(level) => format!("sg-{}", level.name()),
+      Paint::Secondary => "sg-secondary".into(),
+      Paint::Gutter => "sg-gutter".into(),
+      Paint::Bold => "sg-bold".into(),
+    }
+  }
+}
+
+struct Painter(ColorMode);
+
+impl Painter {
+  fn text(&self, text: &str) -> String {
+    if self.0 != ColorMode::Html {
+      return text.to_string();
+    }
+    text
+      .replace('&', "&amp;")
+      .replace('<', "&lt;")
+      .replace('>', "&gt;")
+      .replace('"', "&quot;")
+  }
+
+  fn paint(&self, text: &str, paint: Paint) -> String {
+    let text = self.text(text);
+    if text.is_empty() {
+      return text;
+    }
+    match self.0 {
+      ColorMode::None => text,
+      ColorMode::Ansi => format!("\x1b[{}m{}\x1b[0m", paint.ansi(), text),
+      ColorMode::Html => format!("<span class=\"{}\">{}</span>", paint.class(), text),
+    }
+  }
+}
+
+/// zero based (line, column) in chars, end exclusive
+struct Label {
+  primary: bool,
+  start: (usize, usize),
+  end: (usize, usize),
+  message: String,
+}
+
+impl Label {
+  /// marked columns on the line, None if the label does not reach it
+  fn columns(&self, line: usize, text: &str) -> Option<(usize, usize)> {
+    if line < self.start.0 || line > self.end.0 {
+      return None;
+    }
+    let len = text.chars().count();
+    let from = if line == self.start.0 {
+      self.start.1
+    } else {
+      text.chars().take_while(|c| c.is_whitespace()).count()
+    };
+    let to = if line == self.end.0 { self.end.1 } else { len };
+    // empty spans still get one marker
+    Some((from, to.max(from + 1)))
+  }
+}
+
+struct Diagnostic<'a> {
+  rule: &'a RuleConfig<WasmLang>,
+  level: Level,
+  node: &'a WasmMatch,
+  labels: Vec<Label>,
+  help: Option<String>,
+}
+
+pub struct Report<'a> {
+  src: &'a str,
+  lines: Vec<&'a str>,
+  painter: Painter,
+  options: &'a ReportOptions,
+}
+
+impl<'a> Report<'a> {
+  pub fn new(src: &'a str, options: &'a ReportOptions) -> Self {
+    Self {
+      src,
+      lines: src.lines().collect(),
+      painter: Painter(options.color),
+      options,
+    }
+  }
+
+  pub fn render(
+    &self,
+    rules: &[RuleConfig<WasmLang>],
+    matches: &HashMap<String, Vec<WasmMatch>>,
+    edits: &[RuleEdit],
+  ) -> String {
+    let fixes = self.fix_texts(edits);
+    let mut diagnostics = vec![];
+    for rule in rules {
+      let Some(level) = Level::from_severity(&rule.severity) else {
+        continue;
+      };
+      for node in matches.get(&rule.id).into_iter().flatten() {
+        let (line, column, _, _) = node.node.range;
+        let help = fixes
+          .get(&(rule.id.as_str(), (line, column)))
+          .map(|fix| format!("replace with `{}`", fix));
+        diagnostics.push(Diagnostic {
+          rule,
+          level,
+          node,
+          labels: rule_labels(rule, node),
+          help,
+        });
+      }
+    }
+    diagnostics.sort_by_key(|d| (d.node.node.range.0, d.node.node.range.1));
+    let mut out = String::new();
+    for (i, diagnostic) in diagnostics.iter().enumerate() {
+      if i > 0 && !matches!(self.options.style, ReportStyle::Short) {
+        out.push('\n');
+      }
+      self.write_diagnostic(diagnostic, &mut out);
+    }
+    out
+  }
+
+  /// replacement text of each edit by rule id and start position,
+  /// with UTF-16 columns like node ranges
+  fn fix_texts<'e>(&self, edits: &'e [RuleEdit]) -> HashMap<(&'e str, (usize, usize)), String> {
+    let chars: Vec<_> = self.src.chars().collect();
+    let mut starts = vec![0];
+    let newlines = chars.iter().enumerate().filter(|(_, c)| **c == '\n');
+    starts.extend(newlines.map(|(i, _)| i + 1));
+    let mut ret = HashMap::new();
+    for (id, edit) in edits {
+      let line = starts.partition_point(|&s| s <= edit.position).saturating_sub(1);
+      let column = chars[starts[line]..edit.position].iter().map(|c| c.len_utf16()).sum();
+      ret.insert((id.as_str(), (line, column)), edit.inserted_text.iter().collect());
+    }
+    ret
+  }
+
+  fn write_diagnostic(&self, diagnostic: &Diagnostic, out: &mut String) {
+    let p = &self.painter;
+    let level = diagnostic.level;
+    let title = format!("{}[{}]", level.name(), diagnostic.rule.id);
+    let mut header = p.paint(&title, Paint::Header(level));
+    let message = &diagnostic.node.message;
+    if !message.is_empty() {
+      header.push_str(&p.paint(&format!(": {}", message), Paint::Bold));
+    }
+    let (line, column, _, _) = diagnostic.node.node.range;
+    let location = match &self.options.path {
+      Some(path) => format!("{}:{}:{}", path, line + 1, column + 1),
+      None => format!("{}:{}", line + 1, column + 1),
+    };
+    match self.options.style {
+      ReportStyle::Short => {
+        out.push_str(&format!("{}: {}\n", p.text(&location), header));
+        return;
+      }
+      ReportStyle::Medium => {
+        out.push_str(&format!("{}: {}\n", p.text(&location), header));
+        self.write_notes(diagnostic, 1, out);
+        return;
+      }
+      ReportStyle::Rich => out.push_str(&header),
+    }
+    out.push('\n');
+    let first = diagnostic.labels.iter().map(|l| l.start.0).min().unwrap_or(line);
+    let last = diagnostic.labels.iter().map(|l| l.end.0).max().unwrap_or(line);
+    let width = (last + 1).to_string().len();
+    let pad = " ".repeat(width);
+    let arrow = p.paint("┌─", Paint::Gutter);
+    out.push_str(&format!("{} {} {}\n", pad, arrow, p.text(&location)));
+    self.write_gutter(&pad, out);
+    for line in first..=last {
+      self.write_line(diagnostic, line, width, out);
+    }
+    if diagnostic.rule.note.is_some() || diagnostic.help.is_some() {
+      self.write_gutter(&pad, out);
+    }
+    self.write_notes(diagnostic, width, out);
+  }
+
+  fn write_gutter(&self, pad: &str, out: &mut String) {
+    out.push_str(&format!("{} {}\n", pad, self.painter.paint("│", Paint::Gutter)));
+  }
+
+  fn write_line(&self, diagnostic: &Diagnostic, line: usize, width: usize, out: &mut String) {
+    let p = &self.painter;
+    let text = self.lines.get(line).copied().unwrap_or_default();
+    let number = format!("{:>width$} │", line + 1, width = width);
+    out.push_str(&format!("{} {}\n", p.paint(&number, Paint::Gutter), p.text(text)));
+    let marked: Vec<_> = diagnostic
+      .labels
+      .iter()
+      .filter_map(|l| Some((l, l.columns(line, text)?)))
+      .collect();
+    if marked.is_empty() {
+      return;
+    }
+    let messages: Vec<_> = marked
+      .iter()
+      .filter(|(l, _)| l.end.0 == line && !l.message.is_empty())
+      .collect();
+    let gutter = format!("{} {}", " ".repeat(width), p.paint("│", Paint::Gutter));
+    let mut markers = self.markers(text, &marked, diagnostic.level);
+    // a lone label keeps its message on the same line, otherwise each message gets its own
+    if let ([(label, _)], [_]) = (marked.as_slice(), messages.as_slice()) {
+      markers.push(' ');
+      markers.push_str(&p.paint(&label.message, self.label_paint(label, diagnostic.level)));
+      out.push_str(&format!("{} {}\n", gutter, markers));
+      return;
+    }
+    out.push_str(&format!("{} {}\n", gutter, markers));
+    for &&(label, columns) in &messages {
+      let mut markers = self.markers(text, &[(label, columns)], diagnostic.level);
+      markers.push(' ');
+      markers.push_str(&p.paint(&label.message, self.label_paint(label, diagnostic.level)));
+      out.push_str(&format!("{} {}\n", gutter, markers));
+    }
+  }
+
+  /// `^` under primary labels and `-` under secondary ones, keeping tabs aligned
+  fn markers(&self, text: &str, marked: &[(&Label, (usize, usize))], level: Level) -> String {
+    let end = marked.iter().map(|(_, (_, to))| *to).max().unwrap_or(0);
+    let mut columns = vec![None; end];
+    for (label, (from, to)) in marked {
+      for column in &mut columns[*from..*to] {
+        if column.is_none() || label.primary {
+          *column = Some(label.primary);
+        }
+      }
+    }
+    let mut chars = text.chars();
+    let mut out = String::new();
+    let mut run = String::new();
+    let mut current = None;
+    for column in columns {
+      let c = chars.next();
+      if column != current {
+        out.push_str(&self.paint_run(&run, current, level));
+        run.clear();
+        current = column;
+      }
+      run.push(match (column, c) {
+        (Some(true), _) => '^',
+        (Some(false), _) => '-',
+        (None, Some('\t')) => '\t',
+        (None, _) => ' ',
+      });
+    }
+    out.push_str(&self.paint_run(&run, current, level));
+    out
+  }
+
+  fn paint_run(&self, run: &str, primary: Option<bool>, level: Level) -> String {
+    match primary {
+      Some(true) => self.painter.paint(run, Paint::Primary(level)),
+      Some(false) => self.painter.paint(run, Paint::Secondary),
+      None => run.to_string(),
+    }
+  }
+
+  fn label_paint(&self, label: &Label, level: Level) -> Paint {
+    if label.primary {
+      Paint::Primary(level)
+    } else {
+      Paint::Secondary
+    }
+  }
+
+  fn write_notes(&self, diagnostic: &Diagnostic, width: usize, out: &mut String) {
+    let notes = [("note", diagnostic.rule.note.as_ref()), ("help", diagnostic.help.as_ref())];
+    for (kind, text) in notes.iter() {
+      let Some(text) = text else {
+        continue;
+      };
+      // continuation lines align with the text after `= kind: `
+      let indent = " ".repeat(width + kind.len() + 5);
+      let kind = self.painter.paint(kind, Paint::Bold);
+      for (i, line) in text.trim_end().lines().enumerate() {
+        if i == 0 {
+          out.push_str(&format!("{} = {}: ", " ".repeat(width), kind));
+        } else {
+          out.push_str(&indent);
+        }
+        out.push_str(&self.painter.text(line));
+        out.push('\n');
+      }
+    }
+  }
+}
+
+/// labels configured by the rule, or a primary label on the matched node
+fn rule_labels(rule: &RuleConfig<WasmLang>, node: &WasmMatch) -> Vec<Label> {
+  let range = |r: (usize, usize, usize, usize)| ((r.0, r.1), (r.2, r.3));
+  let mut labels = vec![];
+  if let Some(configs) = &rule.labels {
+    let mut names: Vec<_> = configs.keys().collect();
+    names.sort();
+    for name in names {
+      // transformed variables have no position
+      let Some(var) = node.env.get(name).filter(|v| v.range != (0, 0, 0, 0)) else {
+        continue;
+      };
+      let config = &configs[name];
+      let (start, end) = range(var.range);
+      labels.push(Label {
+        primary: matches!(config.style, LabelStyle::Primary),
+        start,
+        end,
+        message: config.message.clone().unwrap_or_default(),
+      });
+    }
+  }
+  if !labels.iter().any(|l| l.primary) {
+    let (start, end) = range(node.node.range);
+    labels.insert(0, Label {
+      primary: true,
+      start,
+      end,
+      message: String::new(),
+    });
+  }
+  labels
+}
+
+#[cfg(all(test, feature = "native"))]
+mod test {
+  use super::*;
+  use crate::test::use_langs;
+  use crate::{find_matches, fix_edits, rule_from_value};
+  use serde_json::{json, Value};
+
+  fn render(src: &str, rule: Value, options: ReportOptions) -> String {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let rules = vec![rule_from_value(rule).expect("rule should be valid")];
+    let matches = find_matches(src.into(), &rules, &Default::default()).expect("should parse");
+    let edits = fix_edits(src, WasmLang::JavaScript, &rules).unwrap_or_else(|_| panic!("should fix"));
+    Report::new(src, &options).render(&rules, &matches, &edits)
+  }
+
+  fn log_rule() -> Value {
+    json!({
+      "id": "no-log",
+      "language": "javascript",
+      "severity": "warning",
+      "message": "remove log",
+      "note": "use logger",
+      "rule": { "pattern": "console.log($A)" },
+      "fix": "logger.log($A)",
+    })
+  }
+
+  #[test]
+  fn test_rich_report() {
+    let report = render("foo()\nconsole.log(a)\n", log_rule(), Default::default());
+    let expected = "\
+warning[no-log]: remove log
+  ┌─ 2:1
+  │
+2 │ console.log(a)
+  │ ^^^^^^^^^^^^^^
+  │
+  = note: use logger
+  = help: replace with `logger.log(a)`
+";
+    assert_eq!(report, expected);
+  }
+
+  #[test]
+  fn test_fix_after_emoji() {
+    let report = render("f('😀'); console.log(a)", log_rule(), Default::default());
+    assert!(report.contains("= help: replace with `logger.log(a)`"));
+  }
+
+  #[test]
+  fn test_labels() {
+    let mut rule = log_rule();
+    rule["labels"] = json!({ "A": { "style": "secondary", "message": "argument" } });
+    let report = render("console.log(a)", rule, Default::default());
+    assert!(report.contains("1 │ console.log(a)\n  │ ^^^^^^^^^^^^^^\n  │             - argument\n"));
+  }
+
+  #[test]
+  fn test_short_report() {
+    let options = ReportOptions {
+      style: ReportStyle::Short,
+      color: ColorMode::Html,
+      path: Some("<a>.js".into()),
+    };
+    let report = render("console.log(a)\nconsole.log(b)", log_rule(), options);
+    let header = "<span class=\"sg-header sg-warning\">warning[no-log]</span>";
+    let message = "<span class=\"sg-bold\">: remove log</span>";
+    let expected = format!("&lt;a&gt;.js:1:1: {h}{m}\n&lt;a&gt;.js:2:1: {h}{m}\n", h = header, m = message);
+    assert_eq!(report, expected);
+  }
+}
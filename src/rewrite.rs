@@ -0,0 +1,303 @@
+use crate::error_message;
+use crate::utils::{WasmMatch, WasmNode};
+use crate::wasm_lang::{WasmDoc, WasmLang};
+use ast_grep_config::{Fixer, RuleConfig, RuleCore, SerializableRuleConfig, Transformation};
+use ast_grep_core::meta_var::{MetaVarEnv, MetaVariable};
+use ast_grep_core::{Matcher, Node as SgNode, NodeMatch as SgNodeMatch};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ops::Range;
+
+type Node<'a> = SgNode<'a, WasmDoc>;
+type NodeMatch<'a> = SgNodeMatch<'a, WasmDoc>;
+
+/// rewriters calling themselves are bounded by the tree depth, this only guards cycles
+const MAX_DEPTH: usize = 64;
+
+/// a rewriter applied to a node while computing a `transform.rewrite` variable
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RewriteTrace {
+  /// the transformed metavariable, without `$`
+  pub variable: String,
+  pub rewriter: String,
+  pub node: WasmNode,
+  pub replacement: String,
+  /// rewrites done by the rewriter's own transform
+  pub nested: Vec<RewriteTrace>,
+}
+
+/// the source rewritten by `replaceAll` and the traced matches of its rule
+#[derive(Serialize)]
+pub struct Replaced {
+  pub fixed: String,
+  pub matches: Vec<WasmMatch>,
+}
+
+/// `transform: { variable: { rewrite: { source, rewriters, joinBy } } }`
+struct RewriteTransform {
+  variable: String,
+  /// the rewritten metavariable, without `$`
+  source: String,
+  rewriters: Vec<String>,
+  join_by: Option<String>,
+}
+
+impl RewriteTransform {
+  /// the rewrite transforms sorted by variable, other transforms are left to ast-grep
+  fn parse(transform: &Option<HashMap<String, Transformation>>, lang: WasmLang) -> Vec<Self> {
+    let Some(transform) = transform else {
+      return vec![];
+    };
+    let mut ret: Vec<_> = transform.iter().filter_map(|(var, trans)| Self::new(var, trans, lang)).collect();
+    ret.sort_by(|a, b| a.variable.cmp(&b.variable));
+    ret
+  }
+
+  /// None for other transforms and for invalid ones, which fail the rule anyway
+  fn new(variable: &str, trans: &Transformation, lang: WasmLang) -> Option<Self> {
+    let parsed = trans.parse(&lang).ok()?;
+    // ast-grep does not export the rewrite transform, read `joinBy` from its serialized form
+    let object = match trans {
+      Transformation::Object(object) => object.clone(),
+      Transformation::Simplied(s) => s.parse().ok()?,
+    };
+    let value = serde_json::to_value(object).ok()?;
+    let rewrite = value.get("rewrite")?;
+    Some(Self {
+      variable: variable.to_string(),
+      source: parsed.used_vars().to_string(),
+      rewriters: parsed.used_rewriters().to_vec(),
+      join_by: rewrite["joinBy"].as_str().map(String::from),
+    })
+  }
+
+  /// the matched variable, which `transform` uses to indent the rewritten text
+  fn source_var(&self, env: &MetaVarEnv<WasmDoc>) -> MetaVariable {
+    if env.get_match(&self.source).is_some() {
+      MetaVariable::Capture(self.source.clone(), true)
+    } else {
+      MetaVariable::MultiCapture(self.source.clone())
+    }
+  }
+}
+
+/// like ast-grep, rewriters do not need to specify the kinds they match
+struct Rewriter {
+  matcher: RuleCore,
+  fixer: Vec<Fixer>,
+  transforms: Vec<RewriteTransform>,
+}
+
+impl Rewriter {
+  fn try_new(value: Value) -> Result<Self, String> {
+    let config: SerializableRuleConfig<WasmLang> = serde_json::from_value(value).map_err(error_message)?;
+    let transforms = RewriteTransform::parse(&config.transform, config.language);
+    let matcher = config.get_matcher(&Default::default()).map_err(error_message)?;
+    let fixer = match &config.fix {
+      Some(fix) => {
+        let env = matcher.get_env(config.language);
+        Fixer::parse(fix, &env, &config.transform).map_err(error_message)?
+      }
+      None => vec![],
+    };
+    Ok(Self {
+      matcher,
+      fixer,
+      transforms,
+    })
+  }
+}
+
+/// replays `transform.rewrite` of a rule to report which rewriter fired on which node
+pub struct RewriteTracer {
+  transforms: Vec<RewriteTransform>,
+  rewriters: HashMap<String, Rewriter>,
+}
+
+impl RewriteTracer {
+  /// None if the rule has no rewrite transform, an error if a rewriter cannot be replayed
+  pub fn new(rule: &RuleConfig<WasmLang>) -> Result<Option<Self>, String> {
+    let transforms = RewriteTransform::parse(&rule.transform, rule.language);
+    if transforms.is_empty() {
+      return Ok(None);
+    }
+    let config = serde_json::to_value(&**rule).map_err(error_message)?;
+    let Some(all) = config["rewriters"].as_array() else {
+      return Ok(None);
+    };
+    let mut rewriters = HashMap::new();
+    for rewriter in all {
+      let Some(id) = rewriter["id"].as_str() else {
+        continue;
+      };
+      let mut rewriter = rewriter.clone();
+      // rewriters share the language, utils and rewriters of the rule
+      rewriter["language"] = json!(rule.language.to_string());
+      rewriter["rewriters"] = json!(all);
+      if rewriter["utils"].is_null() {
+        rewriter["utils"] = config["utils"].clone();
+      }
+      let rewriter = Rewriter::try_new(rewriter)
+        .map_err(|e| format!("rewriter `{}` of rule `{}`: {}", id, rule.id, e))?;
+      rewriters.insert(id.to_string(), rewriter);
+    }
+    Ok(Some(Self {
+      transforms,
+      rewriters,
+    }))
+  }
+
+  pub fn trace(&self, nm: &NodeMatch) -> Vec<RewriteTrace> {
+    let env = nm.get_env();
+    let mut ret = vec![];
+    for transform in &self.transforms {
+      ret.extend(self.rewrite(transform, env, env, 0).0);
+    }
+    ret
+  }
+
+  /// the rewrites done by one transform and the text it computes
+  fn rewrite<'t>(
+    &self,
+    transform: &RewriteTransform,
+    env: &MetaVarEnv<'t, WasmDoc>,
+    enclosing: &MetaVarEnv<'t, WasmDoc>,
+    depth: usize,
+  ) -> (Vec<RewriteTrace>, Vec<char>) {
+    let mut traces = vec![];
+    let mut edits = vec![];
+    let nodes = match env.get_match(&transform.source) {
+      Some(node) => vec![node.clone()],
+      None => env.get_multiple_matches(&transform.source),
+    };
+    if depth > MAX_DEPTH || nodes.is_empty() {
+      return (traces, vec![]);
+    }
+    for node in &nodes {
+      self.visit(node.clone(), transform, enclosing, depth, &mut traces, &mut edits);
+    }
+    let text = if let Some(joiner) = &transform.join_by {
+      let replaced: Vec<_> = edits.into_iter().map(|(_, text)| text).collect();
+      replaced.join(joiner.as_str()).chars().collect()
+    } else {
+      let source = env.get_var_bytes(&transform.source_var(env)).unwrap_or_default();
+      // node ranges count UTF-16 code units
+      let source: Vec<_> = source.iter().collect::<String>().encode_utf16().collect();
+      let offset = nodes[0].range().start;
+      let mut text = vec![];
+      let mut start = 0;
+      for (range, replaced) in edits {
+        text.extend_from_slice(&source[start..range.start - offset]);
+        text.extend(replaced.encode_utf16());
+        start = range.end - offset;
+      }
+      text.extend_from_slice(&source[start.min(source.len())..]);
+      String::from_utf16_lossy(&text).chars().collect()
+    };
+    (traces, text)
+  }
+
+  /// like the rewrite transform, the first matching rewriter wins
+  /// and the descendants of a rewritten node are skipped.
+  /// Rewriters inherit the variables of the rule, not of the rewriters around them.
+  fn visit<'t>(
+    &self,
+    node: Node<'t>,
+    transform: &RewriteTransform,
+    enclosing: &MetaVarEnv<'t, WasmDoc>,
+    depth: usize,
+    traces: &mut Vec<RewriteTrace>,
+    edits: &mut Vec<(Range<usize>, String)>,
+  ) {
+    let mut rewritten_end = 0;
+    for child in node.dfs() {
+      if child.range().start < rewritten_end {
+        continue;
+      }
+      for id in &transform.rewriters {
+        let Some(rewriter) = self.rewriters.get(id) else {
+          continue;
+        };
+        let mut env = Cow::Borrowed(enclosing);
+        let Some(matched) = rewriter.matcher.match_node_with_env(child.clone(), &mut env) else {
+          continue;
+        };
+        let mut env = env.into_owned();
+        let mut nested_traces = vec![];
+        // recompute the transforms of the rewriter with the rule variables
+        for trans in &rewriter.transforms {
+          let (found, text) = self.rewrite(trans, &env, enclosing, depth + 1);
+          env.insert_transformation(&trans.source_var(&env), &trans.variable, text);
+          nested_traces.extend(found);
+        }
+        let nm = NodeMatch::new(matched, env);
+        rewritten_end = child.range().end;
+        let replacement: String = match rewriter.fixer.first() {
+          Some(fixer) => nm.make_edit(&rewriter.matcher, fixer).inserted_text.iter().collect(),
+          None => String::new(),
+        };
+        edits.push((child.range(), replacement.clone()));
+        traces.push(RewriteTrace {
+          variable: transform.variable.clone(),
+          rewriter: id.clone(),
+          node: WasmNode::from(child.clone()),
+          replacement,
+          nested: nested_traces,
+        });
+        break;
+      }
+    }
+  }
+}
+
+#[cfg(all(test, feature = "native"))]
+mod native_test {
+  use super::*;
+  use crate::test::use_langs;
+  use ast_grep_core::AstGrep;
+
+  #[test]
+  fn test_parse_transform() {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let transform = json!({
+      "A": { "rewrite": { "source": "$$$ARGS", "rewriters": ["a", "b"], "joinBy": ", " } },
+      "B": "rewrite($X, rewriters=[c, d], joinBy=' + ')",
+      "C": { "substring": { "source": "$X", "startChar": 1 } },
+    });
+    let transform = serde_json::from_value(transform).expect("transform should be valid");
+    let parsed = RewriteTransform::parse(&Some(transform), WasmLang::JavaScript);
+    assert_eq!(parsed.len(), 2);
+    assert_eq!(parsed[0].source, "ARGS");
+    assert_eq!(parsed[0].rewriters, ["a", "b"]);
+    assert_eq!(parsed[1].variable, "B");
+    assert_eq!(parsed[1].source, "X");
+    assert_eq!(parsed[1].rewriters, ["c", "d"]);
+    assert_eq!(parsed[0].join_by.as_deref(), Some(", "));
+    assert_eq!(parsed[1].join_by.as_deref(), Some(" + "));
+  }
+
+  #[test]
+  fn test_rewrite_text() {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let rule = crate::rule_from_value(json!({
+      "id": "rename-args",
+      "language": "javascript",
+      "rule": { "pattern": "f($$$ARGS)" },
+      "transform": { "NEW": { "rewrite": { "source": "$$$ARGS", "rewriters": ["rename"] } } },
+      "fix": "g($NEW)",
+      "rewriters": [{ "id": "rename", "rule": { "pattern": "a" }, "fix": "b" }],
+    }))
+    .expect("rule should be valid");
+    let tracer = RewriteTracer::new(&rule).expect("rewriters should compile").expect("rule should rewrite");
+    let src = "f('😀', a, c)";
+    let root = AstGrep::doc(WasmDoc::try_new(src.into(), WasmLang::JavaScript).expect("should parse"));
+    let nm = root.root().find(&rule.matcher).expect("should match");
+    let env = nm.get_env();
+    let (traces, text) = tracer.rewrite(&tracer.transforms[0], env, env, 0);
+    assert_eq!(text.iter().collect::<String>(), "'😀', b, c");
+    assert_eq!(traces[0].node.range, (0, 8, 0, 9));
+  }
+}
@@ -0,0 +1,493 @@
+use crate::injection::Injection;
+use crate::utils::WasmMatch;
+use crate::wasm_lang::{WasmDoc, WasmLang};
+use ast_grep_config::{CombinedScan, RuleConfig};
+use ast_grep_core::matcher::{Matcher, MatcherExt};
+use ast_grep_core::AstGrep;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+extern "C" {
+  /// `(ruleId, match) => boolean | void`, returning false stops the scan
+  pub type MatchCallback;
+  #[wasm_bindgen(method, js_name = call)]
+  fn call2(this: &MatchCallback, context: &JsValue, id: &JsValue, m: &JsValue) -> JsValue;
+}
+
+#[cfg(not(feature = "native"))]
+#[wasm_bindgen]
+extern "C" {
+  #[wasm_bindgen(js_namespace = performance)]
+  pub fn now() -> f64;
+}
+
+#[cfg(feature = "native")]
+pub fn now() -> f64 {
+  static START: once_cell::sync::Lazy<std::time::Instant> =
+    once_cell::sync::Lazy::new(std::time::Instant::now);
+  START.elapsed().as_secs_f64() * 1000.0
+}
+
+/// The scan runs synchronously, so JS cannot cancel it while it runs
+/// and only these limits stop it early.
+/// The budget is checked before matching each node, so a single slow match,
+/// e.g. `has` or `inside` with `stopBy: end` on a large tree, runs to completion.
+#[derive(Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ScanBudget {
+  pub time_ms: Option<f64>,
+  pub max_nodes: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ScanStatus {
+  Complete,
+  BudgetExceeded,
+  /// the match callback returned false
+  Stopped,
+  /// every rule has reached the page limit
+  LimitReached,
+}
+
+// checking the clock on every node is too costly
+const CHECK_INTERVAL: usize = 256;
+
+pub struct Budget {
+  limit: ScanBudget,
+  start: f64,
+  pub visited: usize,
+  pub status: ScanStatus,
+}
+
+impl Budget {
+  pub fn new(limit: ScanBudget) -> Self {
+    Self {
+      limit,
+      start: now(),
+      visited: 0,
+      status: ScanStatus::Complete,
+    }
+  }
+
+  /// record a node visit, returns false if the scan should stop before the node
+  pub fn visit(&mut self) -> bool {
+    if self.status != ScanStatus::Complete {
+      return false;
+    }
+    if self.limit.max_nodes.map_or(false, |max| self.visited >= max) {
+      self.status = ScanStatus::BudgetExceeded;
+      return false;
+    }
+    self.visited += 1;
+    if self.visited % CHECK_INTERVAL == 0 && self.limit.time_ms.map_or(false, |ms| now() - self.start > ms) {
+      self.status = ScanStatus::BudgetExceeded;
+    }
+    self.status == ScanStatus::Complete
+  }
+}
+
+struct RuleScan<'r> {
+  rule: &'r RuleConfig<WasmLang>,
+  kinds: HashSet<u16>,
+}
+
+/// A scan over the tree that can stop early, unlike `CombinedScan`.
+/// Like `CombinedScan`, rules without potential kinds are skipped,
+/// but `ast-grep-ignore` comments are not honored, only `findNodes` applies them.
+pub struct Scanner<'r> {
+  rules: Vec<RuleScan<'r>>,
+}
+
+impl<'r> Scanner<'r> {
+  pub fn new(rules: &'r [RuleConfig<WasmLang>], lang: WasmLang) -> Self {
+    let mut scans = vec![];
+    for rule in rules.iter().filter(|r| r.language == lang) {
+      let Some(kinds) = rule.matcher.potential_kinds() else {
+        continue;
+      };
+      let kinds = kinds.iter().map(|k| k as u16).collect();
+      scans.push(RuleScan { rule, kinds });
+    }
+    Self { rules: scans }
+  }
+
+  /// call `on_match` for every match in order, stopping when it returns false
+  pub fn scan_each(
+    &self,
+    root: &AstGrep<WasmDoc>,
+    injection: Option<&Injection>,
+    budget: &mut Budget,
+    mut on_match: impl FnMut(&RuleConfig<WasmLang>, WasmMatch) -> bool,
+  ) {
+    if self.rules.is_empty() {
+      return;
+    }
+    for node in root.root().dfs() {
+      if !budget.visit() {
+        return;
+      }
+      let kind = node.kind_id();
+      for RuleScan { rule, kinds } in &self.rules {
+        if !kinds.contains(&kind) {
+          continue;
+        }
+        let Some(nm) = rule.matcher.match_node(node.clone()) else {
+          continue;
+        };
+        let mut m = WasmMatch::from_match(nm, rule);
+        if let Some(injection) = injection {
+          injection.map_match(&mut m);
+        }
+        if !on_match(rule, m) {
+          budget.status = ScanStatus::Stopped;
+          return;
+        }
+      }
+    }
+  }
+
+  pub fn rule_ids(&self) -> impl Iterator<Item = &str> {
+    self.rules.iter().map(|r| r.rule.id.as_str())
+  }
+}
+
+/// Scanners of the host document and its injections, each running the rules of its language.
+/// The budgeted and streamed scans go through it.
+pub struct RootScan<'a> {
+  docs: Vec<(Scanner<'a>, &'a AstGrep<WasmDoc>, Option<&'a Injection>)>,
+}
+
+impl<'a> RootScan<'a> {
+  pub fn new(
+    root: &'a AstGrep<WasmDoc>,
+    injections: &'a [Injection],
+    rules: &'a [RuleConfig<WasmLang>],
+  ) -> Self {
+    let docs = std::iter::once((*root.root().lang(), root, None))
+      .chain(injections.iter().map(|i| (i.lang, &i.root, Some(i))))
+      .map(|(lang, root, injection)| (Scanner::new(rules, lang), root, injection))
+      .collect();
+    Self { docs }
+  }
+
+  pub fn scan(&self, budget: &mut Budget) -> HashMap<String, Vec<WasmMatch>> {
+    let mut ret: HashMap<String, Vec<WasmMatch>> = HashMap::new();
+    self.scan_each(budget, |rule, m| {
+      ret.entry(rule.id.clone()).or_default().push(m);
+      true
+    });
+    ret
+  }
+
+  /// matches of the host first, then of each injection
+  pub fn scan_each(
+    &self,
+    budget: &mut Budget,
+    mut on_match: impl FnMut(&RuleConfig<WasmLang>, WasmMatch) -> bool,
+  ) {
+    for (scanner, root, injection) in &self.docs {
+      scanner.scan_each(root, *injection, budget, &mut on_match);
+      if budget.status != ScanStatus::Complete {
+        return;
+      }
+    }
+  }
+
+  pub fn rule_ids(&self) -> impl Iterator<Item = &str> {
+    self.docs.iter().flat_map(|(s, _, _)| s.rule_ids())
+  }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BudgetedMatches {
+  pub matches: HashMap<String, Vec<WasmMatch>>,
+  pub status: ScanStatus,
+  pub visited: usize,
+}
+
+#[derive(Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleProfile {
+  id: String,
+  time_ms: f64,
+  /// nodes of the rule's potential kinds, which the rule was tried on
+  visited: usize,
+  /// distinct node kinds the rule was tried on
+  kinds_tried: Vec<String>,
+  match_count: usize,
+}
+
+#[derive(Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScanProfile {
+  pub parse_ms: f64,
+  pub scan_ms: f64,
+  rules: Vec<RuleProfile>,
+}
+
+impl ScanProfile {
+  /// the profile of a rule, shared by the host and injected documents
+  fn rule(&mut self, id: &str) -> &mut RuleProfile {
+    let index = match self.rules.iter().position(|r| r.id == id) {
+      Some(i) => i,
+      None => {
+        self.rules.push(RuleProfile {
+          id: id.to_string(),
+          ..Default::default()
+        });
+        self.rules.len() - 1
+      }
+    };
+    &mut self.rules[index]
+  }
+
+  /// Run each rule in its own `CombinedScan`, so matches are the same as `findNodes`
+  /// and the time of a rule covers its whole pass over the tree.
+  pub fn scan(
+    &mut self,
+    root: &AstGrep<WasmDoc>,
+    injection: Option<&Injection>,
+    rules: &[RuleConfig<WasmLang>],
+    ret: &mut HashMap<String, Vec<WasmMatch>>,
+  ) {
+    let lang = *root.root().lang();
+    let rules: Vec<_> = rules.iter().filter(|r| r.language == lang).collect();
+    if rules.is_empty() {
+      return;
+    }
+    // count the nodes per kind once, outside of the timed scans
+    let mut kinds: HashMap<u16, (String, usize)> = HashMap::new();
+    for node in root.root().dfs() {
+      let entry = kinds.entry(node.kind_id()).or_insert_with(|| (node.kind().to_string(), 0));
+      entry.1 += 1;
+    }
+    for rule in rules {
+      // CombinedScan skips rules without potential kinds
+      let Some(potential) = rule.matcher.potential_kinds() else {
+        continue;
+      };
+      let combined = CombinedScan::new(vec![rule]);
+      let start = now();
+      let scanned = combined.scan(root, false);
+      let time_ms = now() - start;
+      let profile = self.rule(&rule.id);
+      profile.time_ms += time_ms;
+      for (name, count) in potential.iter().filter_map(|k| kinds.get(&(k as u16))) {
+        profile.visited += count;
+        if !profile.kinds_tried.contains(name) {
+          profile.kinds_tried.push(name.clone());
+        }
+      }
+      for (_, matches) in scanned.matches {
+        profile.match_count += matches.len();
+        let found = ret.entry(rule.id.clone()).or_default();
+        found.extend(matches.into_iter().map(|nm| {
+          let mut m = WasmMatch::from_match(nm, rule);
+          if let Some(injection) = injection {
+            injection.map_match(&mut m);
+          }
+          m
+        }));
+      }
+    }
+  }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfiledMatches {
+  pub matches: HashMap<String, Vec<WasmMatch>>,
+  pub profile: ScanProfile,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Page {
+  /// matches to skip per rule
+  pub offset: usize,
+  /// max matches to report per rule
+  pub limit: Option<usize>,
+}
+
+/// count matches per rule and decide which of them fall in the page
+pub struct Paginator {
+  page: Page,
+  pub counts: HashMap<String, usize>,
+}
+
+impl Paginator {
+  pub fn new(page: Page) -> Self {
+    Self {
+      page,
+      counts: HashMap::new(),
+    }
+  }
+
+  pub fn accept(&mut self, id: &str) -> bool {
+    let count = self.counts.entry(id.to_string()).or_default();
+    let index = *count;
+    *count += 1;
+    index >= self.page.offset && self.page.limit.map_or(true, |l| index < self.page.offset + l)
+  }
+
+  /// all rules have filled their page so the scan can stop early
+  pub fn is_full<'a>(&self, mut ids: impl Iterator<Item = &'a str>) -> bool {
+    let Some(limit) = self.page.limit else {
+      return false;
+    };
+    let end = self.page.offset + limit;
+    ids.all(|id| self.counts.get(id).map_or(false, |&c| c >= end))
+  }
+}
+
+impl MatchCallback {
+  /// returns false if the callback asks to stop
+  pub fn emit(&self, id: &str, m: &WasmMatch) -> Result<bool, serde_wasm_bindgen::Error> {
+    let m = serde_wasm_bindgen::to_value(m)?;
+    let ret = self.call2(&JsValue::UNDEFINED, &JsValue::from_str(id), &m);
+    Ok(ret.as_bool() != Some(false))
+  }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamSummary {
+  pub status: ScanStatus,
+  pub visited: usize,
+  /// matches seen per rule, a lower bound if the scan stopped early
+  pub counts: HashMap<String, usize>,
+}
+
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_paginator() {
+    let mut paginator = Paginator::new(Page { offset: 1, limit: Some(2) });
+    let accepted: Vec<_> = (0..4).map(|_| paginator.accept("a")).collect();
+    assert_eq!(accepted, [false, true, true, false]);
+    assert!(!paginator.accept("b"));
+    assert!(paginator.is_full(["a"].iter().copied()));
+    assert!(!paginator.is_full(["a", "b"].iter().copied()));
+    paginator.accept("b");
+    paginator.accept("b");
+    assert!(paginator.is_full(["a", "b"].iter().copied()));
+    assert_eq!(paginator.counts["a"], 4);
+    let mut paginator = Paginator::new(Page::default());
+    assert!((0..10).all(|_| paginator.accept("a")));
+    assert!(!paginator.is_full(["a"].iter().copied()));
+  }
+}
+
+#[cfg(all(test, feature = "native"))]
+mod native_test {
+  use super::*;
+  use crate::rule_from_value;
+  use crate::test::use_langs;
+  use serde_json::json;
+
+  fn rule(id: &str, pattern: &str) -> RuleConfig<WasmLang> {
+    let config = json!({ "id": id, "language": "javascript", "rule": { "pattern": pattern } });
+    rule_from_value(config).expect("rule should be valid")
+  }
+
+  fn parse(src: &str) -> AstGrep<WasmDoc> {
+    AstGrep::doc(WasmDoc::try_new(src.into(), WasmLang::JavaScript).expect("should parse"))
+  }
+
+  fn scan(src: &str, rules: &[RuleConfig<WasmLang>], limit: ScanBudget) -> (Vec<String>, Budget) {
+    let root = parse(src);
+    let mut budget = Budget::new(limit);
+    let mut found = vec![];
+    RootScan::new(&root, &[], rules).scan_each(&mut budget, |rule, m| {
+      found.push(format!("{}: {}", rule.id, m.node.text));
+      true
+    });
+    (found, budget)
+  }
+
+  #[test]
+  fn test_budget_nodes() {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let rules = [rule("a", "a($X)")];
+    let src = "a(1); a(2); a(3)";
+    let (all, budget) = scan(src, &rules, Default::default());
+    assert_eq!(all, ["a: a(1)", "a: a(2)", "a: a(3)"]);
+    assert_eq!(budget.status, ScanStatus::Complete);
+    let total = budget.visited;
+    let max_nodes = Some(total);
+    let (found, budget) = scan(src, &rules, ScanBudget { max_nodes, time_ms: None });
+    assert_eq!((found.len(), budget.status), (3, ScanStatus::Complete));
+    let mut partial = false;
+    for max in 0..total {
+      let limit = ScanBudget { max_nodes: Some(max), time_ms: None };
+      let (found, budget) = scan(src, &rules, limit);
+      assert_eq!(budget.status, ScanStatus::BudgetExceeded);
+      assert_eq!(budget.visited, max);
+      assert_eq!(found, all[..found.len()]);
+      partial |= !found.is_empty() && found.len() < all.len();
+    }
+    assert!(partial, "some budget should stop between matches");
+  }
+
+  #[test]
+  fn test_budget_time() {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let src = "a(1);\n".repeat(200);
+    let limit = ScanBudget { max_nodes: None, time_ms: Some(0.0) };
+    let (found, budget) = scan(&src, &[rule("a", "a($X)")], limit);
+    assert_eq!(budget.status, ScanStatus::BudgetExceeded);
+    // the clock is only read every CHECK_INTERVAL nodes
+    assert_eq!(budget.visited, CHECK_INTERVAL);
+    assert!(!found.is_empty() && found.len() < 200);
+  }
+
+  #[test]
+  fn test_scan_page() {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let rules = [rule("a", "a($X)"), rule("b", "b($X)")];
+    let src = "a(1); b(1); a(2); b(2); a(3)";
+    let (all, _) = scan(src, &rules, Default::default());
+    assert_eq!(all, ["a: a(1)", "b: b(1)", "a: a(2)", "b: b(2)", "a: a(3)"]);
+    let root = parse(src);
+    let scan = RootScan::new(&root, &[], &rules);
+    let ids: Vec<_> = scan.rule_ids().collect();
+    let mut paginator = Paginator::new(Page { offset: 1, limit: Some(1) });
+    let mut budget = Budget::new(Default::default());
+    let mut page = vec![];
+    scan.scan_each(&mut budget, |rule, m| {
+      if paginator.accept(&rule.id) {
+        page.push(m.node.text);
+      }
+      !paginator.is_full(ids.iter().copied())
+    });
+    assert_eq!(page, ["a(2)", "b(2)"]);
+    assert_eq!(budget.status, ScanStatus::Stopped);
+    assert_eq!(paginator.counts["a"], 2);
+  }
+
+  #[test]
+  fn test_profile() {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let rules = [rule("a", "a($X)"), rule("b", "b")];
+    let root = parse("a(1);\n// ast-grep-ignore\na(2);\nb(a(3))");
+    let mut profile = ScanProfile::default();
+    let mut matches = HashMap::new();
+    profile.scan(&root, None, &rules, &mut matches);
+    assert_eq!(matches["a"].len(), 2);
+    assert_eq!(matches["b"].len(), 1);
+    let a = &profile.rules[0];
+    assert_eq!((a.id.as_str(), a.visited, a.match_count), ("a", 4, 2));
+    assert_eq!(a.kinds_tried, ["call_expression"]);
+    let b = &profile.rules[1];
+    assert_eq!((b.id.as_str(), b.visited, b.match_count), ("b", 4, 1));
+    assert_eq!(b.kinds_tried, ["identifier"]);
+    assert!(a.time_ms >= 0.0 && b.time_ms >= 0.0);
+  }
+}
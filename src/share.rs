@@ -0,0 +1,312 @@
+use crate::rule_from_value;
+use crate::utils::WasmMatch;
+use crate::wasm_lang::WasmLang;
+use base64::alphabet;
+use base64::engine::general_purpose::{GeneralPurpose, GeneralPurposeConfig, URL_SAFE_NO_PAD};
+use base64::engine::DecodePaddingMode;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// version written by `encode_state`, older links are migrated on decode
+pub const SHARE_VERSION: u32 = 2;
+
+/// decompressed states larger than this are rejected
+const MAX_STATE_SIZE: usize = 16 * 1024 * 1024;
+
+const STRICTNESS: [&str; 6] = ["cst", "smart", "ast", "relaxed", "signature", "template"];
+
+/// version 1 links are `btoa` output, which may have lost its padding in the url
+const LEGACY: GeneralPurpose = GeneralPurpose::new(
+  &alphabet::STANDARD,
+  GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Mode {
+  #[default]
+  #[serde(alias = "patch")]
+  Patch,
+  #[serde(alias = "config")]
+  Config,
+}
+
+/// the playground state stored in share links
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ShareState {
+  pub mode: Mode,
+  pub lang: String,
+  pub query: String,
+  pub rewrite: String,
+  pub strictness: String,
+  pub selector: String,
+  pub config: String,
+  pub source: String,
+}
+
+impl Default for ShareState {
+  fn default() -> Self {
+    Self {
+      mode: Mode::Patch,
+      lang: "javascript".into(),
+      query: String::new(),
+      rewrite: String::new(),
+      strictness: "smart".into(),
+      selector: String::new(),
+      config: String::new(),
+      source: String::new(),
+    }
+  }
+}
+
+#[derive(Debug)]
+pub enum ShareError {
+  Base64(base64::DecodeError),
+  Compression,
+  Json(serde_json::Error),
+  Yaml(serde_yaml::Error),
+  UnsupportedVersion(u32),
+}
+
+impl fmt::Display for ShareError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ShareError::Base64(e) => write!(f, "Invalid share link: {}", e),
+      ShareError::Compression => write!(f, "Invalid share link: cannot decompress state"),
+      ShareError::Json(e) => write!(f, "Invalid share state: {}", e),
+      ShareError::Yaml(e) => write!(f, "Invalid rule config: {}", e),
+      ShareError::UnsupportedVersion(v) => write!(
+        f,
+        "Share link version {} is newer than the supported version {}",
+        v, SHARE_VERSION
+      ),
+    }
+  }
+}
+
+impl std::error::Error for ShareError {}
+
+impl From<base64::DecodeError> for ShareError {
+  fn from(err: base64::DecodeError) -> Self {
+    ShareError::Base64(err)
+  }
+}
+impl From<serde_json::Error> for ShareError {
+  fn from(err: serde_json::Error) -> Self {
+    ShareError::Json(err)
+  }
+}
+impl From<serde_yaml::Error> for ShareError {
+  fn from(err: serde_yaml::Error) -> Self {
+    ShareError::Yaml(err)
+  }
+}
+
+/// `v2.` followed by url safe base64 of the deflated json
+pub fn encode_state(state: &ShareState) -> Result<String, ShareError> {
+  let json = serde_json::to_vec(state)?;
+  let compressed = miniz_oxide::deflate::compress_to_vec(&json, 9);
+  Ok(format!("v{}.{}", SHARE_VERSION, URL_SAFE_NO_PAD.encode(compressed)))
+}
+
+/// accept a bare state, a `#state` hash or a whole playground url
+pub fn decode_state(encoded: &str) -> Result<ShareState, ShareError> {
+  let encoded = encoded.rsplit('#').next().unwrap_or_default().trim();
+  let (version, payload) = match encoded.strip_prefix('v').and_then(|s| s.split_once('.')) {
+    Some((version, payload)) => match version.parse() {
+      Ok(version) => (version, payload),
+      Err(_) => (1, encoded),
+    },
+    None => (1, encoded),
+  };
+  if version > SHARE_VERSION {
+    return Err(ShareError::UnsupportedVersion(version));
+  }
+  let json = if version == 1 {
+    // `btoa` padding is often percent encoded when the link is copied
+    LEGACY.decode(encoded.trim_end_matches("%3D").trim_end_matches('='))?
+  } else {
+    let compressed = URL_SAFE_NO_PAD.decode(payload)?;
+    miniz_oxide::inflate::decompress_to_vec_with_limit(&compressed, MAX_STATE_SIZE)
+      .map_err(|_| ShareError::Compression)?
+  };
+  let value: Value = serde_json::from_slice(&json)?;
+  migrate(value, version)
+}
+
+/// upgrade a state written by an older playground to the current shape
+fn migrate(mut value: Value, version: u32) -> Result<ShareState, ShareError> {
+  if version < 2 {
+    // version 1 states predate `strictness` and `selector`,
+    // and a few were written with capitalized language names
+    if let Some(obj) = value.as_object_mut() {
+      obj.retain(|_, v| !v.is_null());
+      if let Some(lang) = obj.get("lang").and_then(Value::as_str) {
+        let lang = lang.to_lowercase();
+        obj.insert("lang".into(), json!(lang));
+      }
+      if obj.get("strictness").and_then(Value::as_str) == Some("") {
+        obj.remove("strictness");
+      }
+    }
+  }
+  Ok(serde_json::from_value(value)?)
+}
+
+impl ShareState {
+  /// build rules the same way as the playground,
+  /// a pattern in patch mode or every yaml document of the config
+  pub fn rule_values(&self) -> Result<Vec<Value>, ShareError> {
+    if self.mode == Mode::Patch && !self.query.is_empty() {
+      let mut pattern = json!({ "context": self.query });
+      if !self.strictness.is_empty() {
+        pattern["strictness"] = json!(self.strictness);
+      }
+      if !self.selector.is_empty() {
+        pattern["selector"] = json!(self.selector);
+      }
+      return Ok(vec![json!({
+        "id": "test-rule",
+        "language": self.lang,
+        "rule": { "pattern": pattern },
+        "fix": self.rewrite,
+      })]);
+    }
+    let mut rules = vec![];
+    // like the playground, only rules without an id take a number
+    let mut unnamed = 0;
+    for doc in serde_yaml::Deserializer::from_str(&self.config) {
+      let mut rule = Value::deserialize(doc)?;
+      let Some(obj) = rule.as_object_mut() else {
+        continue;
+      };
+      if obj.get("id").map_or(true, |id| id.is_null() || id == "") {
+        obj.insert("id".into(), json!(format!("test-rule-{}", unnamed)));
+        unnamed += 1;
+      }
+      obj.insert("language".into(), json!(self.lang));
+      rules.push(rule);
+    }
+    Ok(rules)
+  }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StateIssue {
+  field: &'static str,
+  message: String,
+}
+
+/// list problems that would make the playground fail to reproduce the state
+pub fn validate_state(state: &ShareState) -> Vec<StateIssue> {
+  let mut issues = vec![];
+  let mut issue = |field, message| issues.push(StateIssue { field, message });
+  let lang = match WasmLang::from_str(&state.lang) {
+    Ok(lang) => Some(lang),
+    Err(e) => {
+      issue("lang", e.to_string());
+      None
+    }
+  };
+  if !state.strictness.is_empty() && !STRICTNESS.contains(&state.strictness.as_str()) {
+    let expected = STRICTNESS.join(", ");
+    let message = format!("Unknown strictness `{}`, expected one of {}.", state.strictness, expected);
+    issue("strictness", message);
+  }
+  let field = if state.mode == Mode::Patch && !state.query.is_empty() {
+    "query"
+  } else {
+    "config"
+  };
+  let rules = match state.rule_values() {
+    Ok(rules) => rules,
+    Err(e) => {
+      issue(field, e.to_string());
+      return issues;
+    }
+  };
+  // patterns can only be checked with the parser at hand
+  if lang.map_or(false, |l| l.is_loaded()) {
+    for rule in rules {
+      if let Err(e) = rule_from_value(rule) {
+        issue(field, e);
+      }
+    }
+  }
+  issues
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShareScan {
+  pub matches: HashMap<String, Vec<WasmMatch>>,
+  pub fixed: String,
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  // written before strictness and selector existed, padding dropped by the url
+  const LEGACY_LINK: &str = "/playground#eyJtb2RlIjoiUGF0Y2giLCJsYW5nIjoiUHl0aG9uIiwicXVlcnkiOiJwcmludCgkQSkiLCJyZXdyaXRlIjoibG9nKCRBKSIsInNvdXJjZSI6InByaW50KDEpIn0";
+
+  #[test]
+  fn test_round_trip() {
+    let state = ShareState {
+      query: "console.log($A)".into(),
+      source: "console.log('你好')".into(),
+      ..Default::default()
+    };
+    let encoded = encode_state(&state).expect("should encode");
+    assert!(encoded.starts_with("v2."));
+    let decoded = decode_state(&encoded).expect("should decode");
+    assert_eq!(decoded.query, state.query);
+    assert_eq!(decoded.source, state.source);
+    assert_eq!(decoded.strictness, "smart");
+  }
+
+  #[test]
+  fn test_migrate_legacy() {
+    let state = decode_state(LEGACY_LINK).expect("should decode");
+    assert_eq!(state.lang, "python");
+    assert_eq!(state.query, "print($A)");
+    assert_eq!(state.strictness, "smart");
+    assert_eq!(state.selector, "");
+    assert!(state.mode == Mode::Patch);
+  }
+
+  #[test]
+  fn test_unsupported_version() {
+    assert!(matches!(decode_state("v9.abc"), Err(ShareError::UnsupportedVersion(9))));
+  }
+
+  #[test]
+  fn test_rule_values() {
+    let state = ShareState {
+      mode: Mode::Config,
+      lang: "tsx".into(),
+      config: "rule:\n  kind: jsx_element\n---\nid: named\nrule:\n  pattern: $A\n---\nrule:\n  kind: jsx_text".into(),
+      ..Default::default()
+    };
+    let rules = state.rule_values().expect("should parse yaml");
+    let ids: Vec<_> = rules.iter().map(|r| r["id"].as_str().unwrap_or_default()).collect();
+    assert_eq!(ids, ["test-rule-0", "named", "test-rule-1"]);
+    assert_eq!(rules[1]["language"], "tsx");
+  }
+
+  #[test]
+  fn test_validate() {
+    let state = ShareState {
+      lang: "cobol".into(),
+      strictness: "loose".into(),
+      ..Default::default()
+    };
+    let fields: Vec<_> = validate_state(&state).into_iter().map(|i| i.field).collect();
+    assert_eq!(fields, ["lang", "strictness"]);
+  }
+}
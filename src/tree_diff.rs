@@ -0,0 +1,406 @@
+//! Align two syntax trees in the spirit of GumTree: identical subtrees first,
+//! then containers sharing matched children, then remaining children by kind.
+//! Only leaf text is compared, so whitespace and indentation never show up.
+
+use crate::utils::WasmNode;
+use crate::wasm_lang::WasmDoc;
+use ast_grep_core::Node;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ChangeKind {
+  Inserted,
+  Deleted,
+  Moved,
+  Updated,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeChange {
+  pub change: ChangeKind,
+  pub kind: String,
+  /// None for inserted nodes
+  pub before: Option<WasmNode>,
+  /// None for deleted nodes
+  pub after: Option<WasmNode>,
+}
+
+struct Item<'r> {
+  node: Node<'r, WasmDoc>,
+  parent: Option<usize>,
+  children: Vec<usize>,
+  hash: u64,
+  size: usize,
+}
+
+/// nodes in preorder, so the descendants of a node follow it contiguously
+struct Tree<'r> {
+  items: Vec<Item<'r>>,
+}
+
+impl<'r> Tree<'r> {
+  fn new(root: Node<'r, WasmDoc>) -> Self {
+    let mut tree = Self { items: vec![] };
+    tree.add(root, None);
+    tree
+  }
+
+  fn add(&mut self, node: Node<'r, WasmDoc>, parent: Option<usize>) -> usize {
+    let index = self.items.len();
+    let mut hasher = DefaultHasher::new();
+    node.kind().hash(&mut hasher);
+    self.items.push(Item {
+      node: node.clone(),
+      parent,
+      children: vec![],
+      hash: 0,
+      size: 1,
+    });
+    let children: Vec<_> = node.children().map(|c| self.add(c, Some(index))).collect();
+    if children.is_empty() {
+      node.text().hash(&mut hasher);
+    }
+    for &child in &children {
+      self.items[child].hash.hash(&mut hasher);
+    }
+    let size = children.iter().map(|&c| self.items[c].size).sum::<usize>();
+    let item = &mut self.items[index];
+    item.hash = hasher.finish();
+    item.size += size;
+    item.children = children;
+    index
+  }
+
+  fn is_leaf(&self, index: usize) -> bool {
+    self.items[index].children.is_empty()
+  }
+
+  fn kind(&self, index: usize) -> String {
+    self.items[index].node.kind().to_string()
+  }
+
+  fn text(&self, index: usize) -> String {
+    self.items[index].node.text().to_string()
+  }
+
+  fn wasm_node(&self, index: usize) -> WasmNode {
+    WasmNode::from(self.items[index].node.clone())
+  }
+}
+
+struct Mapping {
+  before: Vec<Option<usize>>,
+  after: Vec<Option<usize>>,
+}
+
+impl Mapping {
+  fn link(&mut self, before: usize, after: usize) {
+    self.before[before] = Some(after);
+    self.after[after] = Some(before);
+  }
+}
+
+pub fn structural_diff(before: Node<WasmDoc>, after: Node<WasmDoc>) -> Vec<NodeChange> {
+  let before = Tree::new(before);
+  let after = Tree::new(after);
+  let mut mapping = Mapping {
+    before: vec![None; before.items.len()],
+    after: vec![None; after.items.len()],
+  };
+  match_identical(&before, &after, &mut mapping);
+  mapping.link(0, 0);
+  match_containers(&before, &after, &mut mapping);
+  match_children(&before, &after, &mut mapping);
+  let mut changes = collect_changes(&before, &after, &mapping);
+  changes.sort_by_key(|c| {
+    let node = c.after.as_ref().or(c.before.as_ref()).expect("change should have a node");
+    (node.range.0, node.range.1, c.before.is_none())
+  });
+  changes
+}
+
+/// pair the largest identical subtrees, leaves wait for their parents to be aligned
+fn match_identical(before: &Tree, after: &Tree, mapping: &mut Mapping) {
+  let mut by_hash: HashMap<u64, Vec<usize>> = HashMap::new();
+  for (i, item) in after.items.iter().enumerate().filter(|(_, item)| item.size > 1) {
+    by_hash.entry(item.hash).or_default().push(i);
+  }
+  let mut order: Vec<_> = (0..before.items.len()).filter(|&i| before.items[i].size > 1).collect();
+  order.sort_by_key(|&i| Reverse(before.items[i].size));
+  for b in order {
+    if mapping.before[b].is_some() {
+      continue;
+    }
+    let Some(candidates) = by_hash.get(&before.items[b].hash) else {
+      continue;
+    };
+    let found = candidates.iter().find(|&&a| mapping.after[a].is_none() && after.kind(a) == before.kind(b));
+    let Some(&a) = found else {
+      continue;
+    };
+    // identical subtrees have the same shape, so their preorders line up
+    for offset in 0..before.items[b].size {
+      mapping.link(b + offset, a + offset);
+    }
+  }
+}
+
+/// pair unmatched nodes whose children mostly went to the same node
+fn match_containers(before: &Tree, after: &Tree, mapping: &mut Mapping) {
+  for b in (0..before.items.len()).rev() {
+    if mapping.before[b].is_some() || before.is_leaf(b) {
+      continue;
+    }
+    let mut votes: HashMap<usize, usize> = HashMap::new();
+    for &child in &before.items[b].children {
+      let parent = mapping.before[child].and_then(|a| after.items[a].parent);
+      if let Some(p) = parent.filter(|&p| mapping.after[p].is_none()) {
+        *votes.entry(p).or_default() += 1;
+      }
+    }
+    let best = votes
+      .into_iter()
+      .filter(|&(a, _)| after.kind(a) == before.kind(b))
+      .max_by_key(|&(a, count)| (count, Reverse(a)));
+    if let Some((a, count)) = best {
+      let total = before.items[b].children.len() + after.items[a].children.len();
+      if count * 4 >= total {
+        mapping.link(b, a);
+      }
+    }
+  }
+}
+
+/// align the unmatched children of matched nodes by kind, top down
+fn match_children(before: &Tree, after: &Tree, mapping: &mut Mapping) {
+  for b in 0..before.items.len() {
+    let Some(a) = mapping.before[b] else {
+      continue;
+    };
+    let unmatched_before: Vec<_> = before.items[b]
+      .children
+      .iter()
+      .copied()
+      .filter(|&c| mapping.before[c].is_none())
+      .collect();
+    let unmatched_after: Vec<_> = after.items[a]
+      .children
+      .iter()
+      .copied()
+      .filter(|&c| mapping.after[c].is_none())
+      .collect();
+    let pairs = common_subsequence(&unmatched_before, &unmatched_after, |x, y| {
+      before.kind(x) == after.kind(y)
+    });
+    for (x, y) in pairs {
+      mapping.link(x, y);
+    }
+  }
+}
+
+fn collect_changes(before: &Tree, after: &Tree, mapping: &Mapping) -> Vec<NodeChange> {
+  let mut changes = vec![];
+  let change = |change, b: Option<usize>, a: Option<usize>| NodeChange {
+    change,
+    kind: b.map(|b| before.kind(b)).or_else(|| a.map(|a| after.kind(a))).unwrap_or_default(),
+    before: b.map(|b| before.wasm_node(b)),
+    after: a.map(|a| after.wasm_node(a)),
+  };
+  let mut updated_parents = HashSet::new();
+  for b in 0..before.items.len() {
+    let Some(a) = mapping.before[b] else {
+      match before.items[b].parent {
+        Some(parent) if mapping.before[parent].is_none() => {}
+        // punctuation and operators are reported as a change of their parent
+        Some(parent) if !before.items[b].node.is_named() => {
+          updated_parents.insert(parent);
+        }
+        _ => changes.push(change(ChangeKind::Deleted, Some(b), None)),
+      }
+      continue;
+    };
+    if before.is_leaf(b) && after.is_leaf(a) && before.text(b) != after.text(a) {
+      changes.push(change(ChangeKind::Updated, Some(b), Some(a)));
+    }
+    let parents = (before.items[b].parent, after.items[a].parent);
+    let same_parent = match parents {
+      (Some(pb), Some(pa)) => mapping.before[pb] == Some(pa),
+      (None, None) => true,
+      _ => false,
+    };
+    if !same_parent {
+      changes.push(change(ChangeKind::Moved, Some(b), Some(a)));
+    }
+    for child in reordered(before, after, mapping, b, a) {
+      changes.push(change(ChangeKind::Moved, Some(child), mapping.before[child]));
+    }
+  }
+  for a in 0..after.items.len() {
+    if mapping.after[a].is_some() {
+      continue;
+    }
+    match after.items[a].parent {
+      Some(parent) if mapping.after[parent].is_none() => {}
+      Some(parent) if !after.items[a].node.is_named() => {
+        updated_parents.extend(mapping.after[parent]);
+      }
+      _ => changes.push(change(ChangeKind::Inserted, None, Some(a))),
+    }
+  }
+  let mut updated_parents: Vec<_> = updated_parents.into_iter().collect();
+  updated_parents.sort_unstable();
+  for b in updated_parents {
+    changes.push(change(ChangeKind::Updated, Some(b), mapping.before[b]));
+  }
+  changes
+}
+
+/// children that stayed under the same parent but not in the same order
+fn reordered(before: &Tree, after: &Tree, mapping: &Mapping, b: usize, a: usize) -> Vec<usize> {
+  let siblings = &after.items[a].children;
+  let kept: Vec<(usize, usize)> = before.items[b]
+    .children
+    .iter()
+    .filter_map(|&child| {
+      let position = siblings.iter().position(|&s| Some(s) == mapping.before[child])?;
+      Some((child, position))
+    })
+    .collect();
+  let positions: Vec<_> = kept.iter().map(|&(_, p)| p).collect();
+  let in_order = increasing_subsequence(&positions);
+  kept
+    .iter()
+    .enumerate()
+    .filter(|(i, _)| !in_order.contains(i))
+    .map(|(_, &(child, _))| child)
+    .collect()
+}
+
+/// pairs of a longest common subsequence
+fn common_subsequence(
+  xs: &[usize],
+  ys: &[usize],
+  same: impl Fn(usize, usize) -> bool,
+) -> Vec<(usize, usize)> {
+  let mut table = vec![vec![0usize; ys.len() + 1]; xs.len() + 1];
+  for i in (0..xs.len()).rev() {
+    for j in (0..ys.len()).rev() {
+      table[i][j] = if same(xs[i], ys[j]) {
+        table[i + 1][j + 1] + 1
+      } else {
+        table[i + 1][j].max(table[i][j + 1])
+      };
+    }
+  }
+  let (mut i, mut j) = (0, 0);
+  let mut pairs = vec![];
+  while i < xs.len() && j < ys.len() {
+    if same(xs[i], ys[j]) {
+      pairs.push((xs[i], ys[j]));
+      i += 1;
+      j += 1;
+    } else if table[i + 1][j] >= table[i][j + 1] {
+      i += 1;
+    } else {
+      j += 1;
+    }
+  }
+  pairs
+}
+
+/// indices of a longest strictly increasing subsequence
+fn increasing_subsequence(values: &[usize]) -> HashSet<usize> {
+  let mut tails: Vec<usize> = vec![];
+  let mut previous = vec![None; values.len()];
+  for (i, &value) in values.iter().enumerate() {
+    let slot = tails.partition_point(|&t| values[t] < value);
+    previous[i] = slot.checked_sub(1).map(|s| tails[s]);
+    if slot == tails.len() {
+      tails.push(i);
+    } else {
+      tails[slot] = i;
+    }
+  }
+  let mut ret = HashSet::new();
+  let mut current = tails.last().copied();
+  while let Some(i) = current {
+    ret.insert(i);
+    current = previous[i];
+  }
+  ret
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_increasing_subsequence() {
+    let kept = increasing_subsequence(&[1, 2, 0]);
+    assert_eq!(kept, [0, 1].iter().copied().collect());
+    assert!(increasing_subsequence(&[]).is_empty());
+  }
+
+  #[test]
+  fn test_common_subsequence() {
+    let pairs = common_subsequence(&[1, 2, 3], &[2, 3, 4], |x, y| x == y);
+    assert_eq!(pairs, [(2, 2), (3, 3)]);
+  }
+}
+
+#[cfg(all(test, feature = "native"))]
+mod native_test {
+  use super::*;
+  use crate::test::use_langs;
+  use crate::wasm_lang::WasmLang;
+  use ast_grep_core::AstGrep;
+
+  fn diff(before: &str, after: &str) -> Vec<NodeChange> {
+    let _lock = use_langs(&[WasmLang::JavaScript]);
+    let parse = |src: &str| AstGrep::doc(WasmDoc::try_new(src.into(), WasmLang::JavaScript).expect("should parse"));
+    let (before, after) = (parse(before), parse(after));
+    structural_diff(before.root(), after.root())
+  }
+
+  fn summary(changes: &[NodeChange]) -> Vec<(ChangeKind, &str, Option<&str>)> {
+    fn text(n: &Option<WasmNode>) -> Option<&str> {
+      n.as_ref().map(|n| n.text.as_str())
+    }
+    changes
+      .iter()
+      .map(|c| (c.change, text(&c.before).or_else(|| text(&c.after)).unwrap_or_default(), text(&c.after)))
+      .collect()
+  }
+
+  #[test]
+  fn test_formatting_only() {
+    assert!(diff("foo(a,b)\nbar()", "foo( a ,\n  b )\n\nbar( )").is_empty());
+  }
+
+  #[test]
+  fn test_update_and_move() {
+    let changes = diff("const a = 1;\nfoo(a, b);\nbar();", "bar();\nconst a = 2;\n  foo(a,   c);");
+    assert_eq!(
+      summary(&changes),
+      [
+        (ChangeKind::Moved, "bar();", Some("bar();")),
+        (ChangeKind::Updated, "1", Some("2")),
+        (ChangeKind::Updated, "b", Some("c")),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_insert_and_delete() {
+    let changes = diff("a();\nb();", "a();\nlet c = 1;");
+    let kinds: Vec<_> = changes.iter().map(|c| c.change).collect();
+    assert_eq!(kinds, [ChangeKind::Deleted, ChangeKind::Inserted]);
+    assert_eq!(changes[0].kind, "expression_statement");
+  }
+}
@@ -1,3 +1,4 @@
+use crate::rewrite::RewriteTrace;
 use crate::wasm_lang::{WasmLang, WasmDoc};
 use ast_grep_core::{
   meta_var::{MetaVarEnv, MetaVariable},
@@ -29,21 +30,34 @@ pub struct WasmNode {
 
 #[derive(Serialize, Deserialize)]
 pub struct WasmMatch {
+  /// same as the `id` of the node in `dumpASTNodes` of the same source
+  pub id: usize,
   pub kind: String,
   pub node: WasmNode,
   pub env: BTreeMap<String, WasmNode>,
   pub message: String,
+  /// rewriters applied by `transform.rewrite`, in the order they fired
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub rewrites: Vec<RewriteTrace>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct FindOptions {
+  /// replay `transform.rewrite` to report the `rewrites` of each match
+  pub trace_rewrites: bool,
 }
 
 impl WasmMatch {
   pub fn from_match(nm: NodeMatch, rule: &RuleConfig<WasmLang>) -> Self {
     let node = nm.get_node().clone();
+    let id = node.node_id();
     let kind = node.kind().to_string();
     let node = WasmNode::from(node);
     let env = nm.get_env().clone();
     let env = env_to_map(env);
     let message = rule.get_message(&nm);
-    Self { node, env, message, kind }
+    Self { id, node, env, message, kind, rewrites: vec![] }
   }
 }
 
@@ -59,7 +73,7 @@ fn env_to_map(env: MetaVarEnv<'_, WasmDoc>) -> BTreeMap<String, WasmNode> {
             text: bytes.iter().collect(),
             range: (0, 0, 0, 0),
           };
-          map.insert(name, WasmNode::from(node));
+          map.insert(name, node);
         }
       }
       MetaVariable::MultiCapture(name) => {
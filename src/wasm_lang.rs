@@ -5,14 +5,17 @@ use ast_grep_core::source::{Content, Doc, Edit, SgNode};
 use ast_grep_core::matcher::{PatternBuilder, PatternError, Pattern};
 use ast_grep_core::Position;
 use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::ops::Range;
 use std::sync::Mutex;
-use web_tree_sitter_sg::{SyntaxNode, Parser, Point, Tree};
-use web_tree_sitter_sg as ts;
+use crate::grammar::Grammar;
+use crate::ts::{self, SyntaxNode, Parser, Point, Tree};
 use wasm_bindgen::prelude::*;
-use serde::{Deserialize, Deserializer, de};
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de};
+use once_cell::sync::Lazy;
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum WasmLang {
   JavaScript,
   TypeScript,
@@ -91,6 +94,40 @@ impl FromStr for WasmLang {
   }
 }
 
+impl std::fmt::Display for WasmLang {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let name = match self {
+      JavaScript => "javascript",
+      TypeScript => "typescript",
+      Tsx => "tsx",
+      Bash => "bash",
+      C => "c",
+      CSharp => "csharp",
+      Css => "css",
+      Cpp => "cpp",
+      Dart => "dart",
+      Elixir => "elixir",
+      Go => "go",
+      Hcl => "hcl",
+      Html => "html",
+      Haskell => "haskell",
+      Java => "java",
+      Json => "json",
+      Lua => "lua",
+      Kotlin => "kotlin",
+      Nix => "nix",
+      Php => "php",
+      Python => "python",
+      Ruby => "ruby",
+      Rust => "rust",
+      Scala => "scala",
+      Swift => "swift",
+      Yaml => "yaml",
+    };
+    write!(f, "{}", name)
+  }
+}
+
 impl<'de> Deserialize<'de> for WasmLang {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
         where D: Deserializer<'de>
@@ -100,21 +137,48 @@ impl<'de> Deserialize<'de> for WasmLang {
     }
 }
 
+impl Serialize for WasmLang {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.collect_str(self)
+    }
+}
+
 #[derive(Clone)]
 struct TsLang(ts::Language);
 
 unsafe impl Send for TsLang {}
 unsafe impl Sync for TsLang {}
 
-static TS_LANG: Mutex<Option<TsLang>> = Mutex::new(None);
+static TS_LANGS: Lazy<Mutex<HashMap<WasmLang, TsLang>>> = Lazy::new(Default::default);
 static LANG: Mutex<WasmLang> = Mutex::new(JavaScript);
 
 impl WasmLang {
   pub async fn set_current(lang: &str, parser_path: &str) -> Result<(), JsError> {
     let lang = WasmLang::from_str(lang)?;
+    setup_parser(lang, parser_path).await?;
     let mut curr_lang = LANG.lock().expect_throw("set language error");
     *curr_lang = lang;
-    setup_parser(parser_path).await?;
+    Ok(())
+  }
+
+  /// load a parser without changing the current language,
+  /// e.g. for languages injected in the host document
+  pub async fn register(lang: &str, parser_path: &str) -> Result<(), JsError> {
+    let lang = WasmLang::from_str(lang)?;
+    setup_parser(lang, parser_path).await?;
+    Ok(())
+  }
+
+  /// load a bundled grammar synchronously and make it current
+  #[cfg(feature = "native")]
+  pub fn set_native(lang: WasmLang) -> Result<(), SgWasmError> {
+    let ts_lang = ts::Language::for_lang(lang)?;
+    TS_LANGS.lock().expect_throw("set language error").insert(lang, TsLang(ts_lang));
+    Grammar::forget(lang);
+    WasmDoc::forget_cached();
+    *LANG.lock().expect_throw("set language error") = lang;
     Ok(())
   }
 
@@ -122,38 +186,61 @@ impl WasmLang {
     *LANG.lock().expect_throw("get language error")
   }
 
-  fn get_ts_language(&self) -> ts::Language {
-    TS_LANG
+  pub fn is_loaded(&self) -> bool {
+    TS_LANGS
+      .lock()
+      .expect_throw("get language error")
+      .contains_key(self)
+  }
+
+  pub fn get_ts_language(&self) -> Result<ts::Language, SgWasmError> {
+    TS_LANGS
       .lock()
       .expect_throw("get language error")
-      .clone()
-      .expect_throw("current language is not set")
-      .0
+      .get(self)
+      .map(|lang| lang.0.clone())
+      .ok_or(SgWasmError::LanguageNotLoaded(*self))
   }
 
 }
 
-async fn setup_parser(parser_path: &str) -> Result<(), SgWasmError> {
+async fn setup_parser(lang: WasmLang, parser_path: &str) -> Result<(), SgWasmError> {
   let parser = ts::Parser::new()?;
-  let lang = get_lang(parser_path).await?;
-  parser.set_language(Some(&lang))?;
-  let mut curr_lang = TS_LANG.lock().expect_throw("set language error");
-  *curr_lang = Some(TsLang(lang));
+  let ts_lang = get_lang(lang, parser_path).await?;
+  parser.set_language(Some(&ts_lang))?;
+  TS_LANGS.lock().expect_throw("set language error").insert(lang, TsLang(ts_lang));
+  Grammar::forget(lang);
+  WasmDoc::forget_cached();
   Ok(())
 }
 
-#[cfg(target_arch = "wasm32")]
-async fn get_lang(parser_path: &str) -> Result<ts::Language, SgWasmError> {
+#[cfg(all(target_arch = "wasm32", not(feature = "native")))]
+async fn get_lang(_lang: WasmLang, parser_path: &str) -> Result<ts::Language, SgWasmError> {
   let lang = ts::Language::load_path(parser_path)
     .await?;
   Ok(lang)
 }
 
-#[cfg(not(target_arch = "wasm32"))]
-async fn get_lang(_path: &str) -> Result<ts::Language, SgWasmError> {
+#[cfg(feature = "native")]
+async fn get_lang(lang: WasmLang, _path: &str) -> Result<ts::Language, SgWasmError> {
+  Ok(ts::Language::for_lang(lang)?)
+}
+
+#[cfg(not(any(target_arch = "wasm32", feature = "native")))]
+async fn get_lang(_lang: WasmLang, _path: &str) -> Result<ts::Language, SgWasmError> {
   unreachable!()
 }
 
+#[cfg(not(feature = "native"))]
+pub fn node_children(node: &SyntaxNode) -> Vec<SyntaxNode> {
+  node.children().to_vec().into_iter().map(|n| n.unchecked_into()).collect()
+}
+
+#[cfg(feature = "native")]
+pub fn node_children(node: &SyntaxNode) -> Vec<SyntaxNode> {
+  node.children()
+}
+
 impl Language for WasmLang {
   fn expando_char(&self) -> char {
     use WasmLang as W;
@@ -190,20 +277,22 @@ impl Language for WasmLang {
   fn build_pattern(&self, builder: &PatternBuilder) -> Result<Pattern, PatternError> {
     builder.build(|src| {
       let src = src.to_string();
-      let ret = WasmDoc::try_new(src, self.clone()).map_err(|e| e.to_string());
-      Ok(ret?)
+      WasmDoc::try_new(src, *self).map_err(|e| e.to_string())
     })
   }
 
   fn pre_process_pattern<'q>(&self, query: &'q str) -> Cow<'q, str> {
     pre_process_pattern(self.expando_char(), query)
   }
+  // an unloaded language has no kinds, 0 is reported as an invalid kind
   fn kind_to_id(&self, kind: &str) -> u16 {
-    let lang = self.get_ts_language();
+    let Ok(lang) = self.get_ts_language() else {
+      return 0;
+    };
     lang.id_for_node_kind(kind, true)
   }
   fn field_to_id(&self, field: &str) -> Option<u16> {
-    let lang = self.get_ts_language();
+    let lang = self.get_ts_language().ok()?;
     lang.field_id_for_name(field)
   }
 }
@@ -234,19 +323,24 @@ pub struct Wrapper {
   inner: Vec<char>,
 }
 impl Wrapper {
+  // edits are in chars, tree-sitter counts UTF-16 code units
   fn accept_edit(&mut self, edit: &Edit<Self>) -> ts::Edit {
-    let start_byte = edit.position;
-    let old_end_byte = edit.position + edit.deleted_length;
-    let new_end_byte = edit.position + edit.inserted_text.len();
+    let start = edit.position;
+    let old_end = edit.position + edit.deleted_length;
+    let new_end = edit.position + edit.inserted_text.len();
     let mut input = self.inner.to_vec();
-    let start_position = pos_for_char_offset(&input, start_byte);
-    let old_end_position = pos_for_char_offset(&input, old_end_byte);
-    input.splice(start_byte..old_end_byte, edit.inserted_text.clone());
-    let new_end_position = pos_for_char_offset(&input, new_end_byte);
+    let start_index = utf16_len(&input[..start]);
+    let old_end_index = utf16_len(&input[..old_end]);
+    let start_position = pos_for_char_offset(&input, start);
+    let old_end_position = pos_for_char_offset(&input, old_end);
+    input.splice(start..old_end, edit.inserted_text.clone());
+    let new_end_index = utf16_len(&input[..new_end]);
+    let new_end_position = pos_for_char_offset(&input, new_end);
+    self.inner = input;
     ts::Edit::new(
-      start_byte as u32,
-      old_end_byte as u32,
-      new_end_byte as u32,
+      start_index as u32,
+      old_end_index as u32,
+      new_end_index as u32,
       &start_position,
       &old_end_position,
       &new_end_position,
@@ -254,10 +348,17 @@ impl Wrapper {
   }
 }
 
+fn utf16_len(chars: &[char]) -> usize {
+  chars.iter().map(|c| c.len_utf16()).sum()
+}
+
 impl Content for Wrapper {
   type Underlying = char;
+  // node ranges count UTF-16 code units
   fn get_range(&self, range: Range<usize>) -> &[char] {
-    &self.inner[range]
+    let start = char_offset(&self.inner, range.start);
+    let end = start + char_offset(&self.inner[start..], range.end - range.start);
+    &self.inner[start..end]
   }
   fn decode_str(src: &str) -> Cow<'_, [Self::Underlying]> {
     Cow::Owned(src.chars().collect())
@@ -271,6 +372,15 @@ impl Content for Wrapper {
   }
 }
 
+/// the number of chars spanning `utf16_offset` code units
+fn char_offset(chars: &[char], utf16_offset: usize) -> usize {
+  let mut len = 0;
+  chars.iter().take_while(|c| {
+    len += c.len_utf16();
+    len <= utf16_offset
+  }).count()
+}
+
 fn pos_for_char_offset(input: &[char], offset: usize) -> Point {
   debug_assert!(offset <= input.len());
   let (mut row, mut col) = (0, 0);
@@ -279,7 +389,7 @@ fn pos_for_char_offset(input: &[char], offset: usize) -> Point {
       row += 1;
       col = 0;
     } else {
-      col += 1;
+      col += c.len_utf16() as u32;
     }
   }
   Point::new(row, col)
@@ -296,6 +406,7 @@ pub struct WasmDoc {
 pub enum SgWasmError {
   ParserError(ts::ParserError),
   LanguageError(ts::LanguageError),
+  LanguageNotLoaded(WasmLang),
   FailedToParse,
 }
 
@@ -304,6 +415,7 @@ impl std::fmt::Display for SgWasmError {
     match self {
       SgWasmError::ParserError(err) => write!(f, "Parser error: {}", err.message()),
       SgWasmError::LanguageError(err) => write!(f, "Language error: {}", err.message()),
+      SgWasmError::LanguageNotLoaded(lang) => write!(f, "Language `{}` is not loaded", lang),
       SgWasmError::FailedToParse => write!(f, "Failed to parse"),
     }
   }
@@ -328,13 +440,42 @@ impl WasmDoc {
       inner: src.chars().collect(),
     };
     let parser = Parser::new()?;
-    let ts_lang = lang.get_ts_language();
+    let ts_lang = lang.get_ts_language()?;
     parser.set_language(Some(&ts_lang))?;
     let Some(tree) = parser.parse_with_string(&src.into(), None, None)? else {
       return Err(SgWasmError::FailedToParse);
     };
     Ok(Self { source, lang, tree })
   }
+
+  /// reuse the tree of the last document parsed this way if the source is the same,
+  /// so node ids in dumps and matches of the same source refer to the same nodes
+  pub fn cached(src: String, lang: WasmLang) -> Result<Self, SgWasmError> {
+    LAST_DOC.with(|last| {
+      let mut last = last.borrow_mut();
+      if let Some((text, doc)) = &*last {
+        if doc.lang == lang && *text == src {
+          return Ok(doc.clone());
+        }
+      }
+      let doc = Self::try_new(src.clone(), lang)?;
+      *last = Some((src, doc.clone()));
+      Ok(doc)
+    })
+  }
+
+  /// drop the cached document, its tree may come from a replaced parser
+  fn forget_cached() {
+    LAST_DOC.with(|last| last.borrow_mut().take());
+  }
+
+  pub fn chars(&self) -> &[char] {
+    &self.source.inner
+  }
+}
+
+thread_local! {
+  static LAST_DOC: RefCell<Option<(String, WasmDoc)>> = const { RefCell::new(None) };
 }
 
 #[derive(Clone)]
@@ -360,7 +501,7 @@ impl<'a> SgNode<'a> for Node {
     self.0.child(nth as u32).map(Node)
   }
   fn children(&self) -> impl ExactSizeIterator<Item = Self> {
-    self.0.children().to_vec().into_iter().map(|n| n.unchecked_into::<SyntaxNode>()).map(Node)
+    node_children(&self.0).into_iter().map(Node)
   }
   fn child_by_field_id(&self, field_id: u16) -> Option<Self> {
     self.0.child_for_field_id(field_id).map(Node)
@@ -461,7 +602,7 @@ impl Doc for WasmDoc {
     let edit = self.source.accept_edit(edit);
     self.tree.edit(&edit);
     let parser = Parser::new().map_err(|e| e.to_string())?;
-    let ts_lang = self.lang.get_ts_language();
+    let ts_lang = self.lang.get_ts_language().map_err(|e| e.to_string())?;
     parser.set_language(Some(&ts_lang)).map_err(|e| e.to_string())?;
     let src = self.source.inner.iter().collect::<String>();
     let parse_ret = parser.parse_with_string(&src.into(), Some(&self.tree), None);
@@ -474,4 +615,18 @@ impl Doc for WasmDoc {
   fn get_node_text<'a>(&'a self, node: &Self::Node<'a>) -> Cow<'a, str> {
     Cow::Owned(node.0.text().into())
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_pre_process_pattern() {
+    let processed = pre_process_pattern('µ', "$A = $$$ + $$B + $a");
+    assert_eq!(processed, "µA = µµµ + µµB + $a");
+    assert_eq!(pre_process_pattern('µ', "foo($$$)"), "foo(µµµ)");
+    assert_eq!(pre_process_pattern('µ', "$$$"), "µµµ");
+    assert_eq!(pre_process_pattern('$', "$_ + $$"), "$_ + $$");
+  }
+}
@@ -20,9 +20,16 @@ export interface GeneralNode {
 
 /** stub wasm DumpNode */
 export interface DumpNode extends GeneralNode {
+  id: number
   field: string | undefined
   kind: string
+  range: [number, number]
   isNamed: boolean
+  isError: boolean
+  isMissing: boolean
+  isExtra: boolean
+  text: string | undefined
+  collapsed: boolean
 }
 
 /** stub wasm PatternTree */
@@ -31,6 +38,20 @@ export interface PatternTree extends GeneralNode {
   isNamed: boolean
   text: string | undefined
   pattern?: 'metaVar' | 'terminal' | 'internal'
+  rootKinds?: string[]
+  diagnostics?: SyntaxDiagnostic[]
+}
+
+/** stub wasm SyntaxDiagnostic */
+export interface SyntaxDiagnostic {
+  kind: 'error' | 'missing'
+  message: string
+  start: Pos
+  end: Pos
+  range: [number, number]
+  expected: string | undefined
+  parent: string | undefined
+  context: string
 }
 
 type DestructedNode<T> = {